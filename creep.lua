@@ -5,19 +5,88 @@ JSON = require("JSON")
 
 Fuel = turtle.getItemDetail(16).name
 Instructions = {}
+Failure = nil -- why the current step failed, if it did
 Pos = {
     ["x"] = 0,
     ["y"] = 0,
     ["z"] = 0
 }
 Rotation = 0 -- 0 = N, 1 = E, 2 = S, 3 = W
+-- Which world we're in. Asked for when we register and kept in the computer's
+-- settings; after a portal, run `set turtle.dimension <dimension>` and restart
+settings.define("turtle.server", { description = "Minecraft server this turtle is on", default = "default", type = "string" })
+settings.define("turtle.dimension", { description = "Dimension this turtle is in", default = "minecraft:overworld", type = "string" })
+World = { server = settings.get("turtle.server"), dimension = settings.get("turtle.dimension") }
+Token = nil -- issued by the server when we register, kept in TOKEN_PATH
+TOKEN_PATH = "token"
+
+function AskWorld() -- asks which world we're in and saves it to the settings
+    for _, name in ipairs({ "server", "dimension" }) do
+        local setting = "turtle." .. name
+        print("Enter " .. name .. " (blank for " .. settings.get(setting) .. "): ")
+        local value = read()
+        if value ~= "" then
+            settings.set(setting, value)
+        end
+    end
+    settings.save()
+    World = { server = settings.get("turtle.server"), dimension = settings.get("turtle.dimension") }
+end
+
+function LoadToken() -- reads our token from disk, registering first if we don't have one
+    if fs.exists(TOKEN_PATH) then
+        local file = fs.open(TOKEN_PATH, "r")
+        Token = file.readAll()
+        file.close()
+        return true
+    end
+    AskWorld()
+    -- The registration key is typed in once and never written to disk
+    print("Enter registration key: ")
+    local key = read("*")
+    local response, err, resp = http.post("http://localhost:3001/register", "", {
+        ["turtle-id"]     = tostring(os.getComputerID()),
+        ["authorization"] = key
+    })
+    if not response then
+        print("Registration failed: " .. tostring(err))
+        if resp then print(resp.readAll()) end
+        return false
+    end
+    Token = textutils.unserializeJSON(response.readAll()).token
+    response.close()
+    local file = fs.open(TOKEN_PATH, "w")
+    file.write(Token)
+    file.close()
+    return true
+end
+
+SIGN_REQUESTS = false -- sign requests instead of sending our token, needs hmac.lua
+
+function AuthHeaders(method, path, body, headers) -- adds our credentials to a request's headers
+    headers = headers or {}
+    headers["turtle-id"] = tostring(os.getComputerID())
+    if not SIGN_REQUESTS then
+        headers["authorization"] = Token
+        return headers
+    end
+    -- See `authenticate` in src/auth.rs for what gets signed
+    local hmac = require("hmac")
+    local now = os.epoch("utc")
+    local timestamp = string.format("%d", math.floor(now / 1000))
+    local nonce = string.format("%d-%d-%d", os.getComputerID(), now, math.random(1, 2 ^ 30))
+    local message = method .. "\n" .. path .. "\n" .. timestamp .. "\n" .. nonce .. "\n" .. (body or "")
+    headers["x-timestamp"] = timestamp
+    headers["x-nonce"] = nonce
+    headers["x-signature"] = hmac.hex(hmac.hmac(hmac.sha256(Token), message))
+    return headers
+end
 
 function GetInstructions()
         -- DetermineOrientation()
     print("Current orientation: " .. Rotation)
 
     local body = textutils.serializeJSON({
-        secret_key = "blah",
         start = Pos,
         goal = { x = 12, y = -60, z = 20 }
     })
@@ -25,7 +94,7 @@ function GetInstructions()
     local response = http.post(
         "http://localhost:3001/request-path",
         body,
-        { ["Content-Type"] = "application/json" }
+        AuthHeaders("POST", "/request-path", body, { ["Content-Type"] = "application/json" })
     )
 
     if response then
@@ -51,6 +120,35 @@ function GetInstructions()
     -- return true
 end
 
+function PollInstructions() -- asks for our next batch, or what's left of an unfinished one
+    local headers = AuthHeaders("GET", "/get-instructions")
+    local response = http.get("http://localhost:3001/get-instructions", headers)
+    if not response then
+        print("Failed to get instructions from server.")
+        return false
+    end
+    Instructions = textutils.unserializeJSON(response.readAll())
+    response.close()
+    if #Instructions["steps"] == 0 then
+        return false
+    end
+    RunInstructions()
+    return true
+end
+
+function ReportFailure(step, reason) -- tells the server a step failed so it can fail the job
+    if Socket then
+        SendMessage({ type = "failure", batch = Instructions.id, step = step, reason = reason })
+        return
+    end
+    local body = JSON:encode({ batch = Instructions.id, step = step, reason = reason })
+    local headers = AuthHeaders("POST", "/report-failure", body, { ["Content-Type"] = "application/json" })
+    local response = http.post("http://localhost:3001/report-failure", body, headers)
+    if response then
+        response.close()
+    end
+end
+
 function PostInventory()
     -- Build a list of {slot = n, name = "minecraft:stone", count = 64}
     local inv = {}
@@ -66,17 +164,12 @@ function PostInventory()
     end
 
     local payload = JSON:encode({
-        turtle_id = computer.getLabel(),
         inventory = inv
     })
 
-    local headers = {
-        ["Content-Type"] = "application/json",
-        ["turtle_id"]    = computer.getLabel(),
-        ["authorization"] = "iamarealturtleyesiam"
-    }
+    local headers = AuthHeaders("POST", "/inventory", payload, { ["Content-Type"] = "application/json" })
 
-    local response = http.post("http://localhost/inventory", payload, headers)
+    local response = http.post("http://localhost:3001/inventory", payload, headers)
 
     if response then
         response.close()
@@ -88,7 +181,46 @@ function PostInventory()
 end
 
 
-function PostInfo(i)
+function Scan(radius) -- uploads everything a geoScanner peripheral can see
+    local scanner = peripheral.find("geoScanner")
+    if not scanner then
+        print("No geoScanner attached")
+        return false
+    end
+    local found, err = scanner.scan(radius)
+    if not found then
+        print("Scan failed: " .. tostring(err))
+        return false
+    end
+
+    -- Send each block name once and refer to it by index
+    local palette, lookup, blocks = {}, {}, {}
+    for _, b in ipairs(found) do
+        if not lookup[b.name] then
+            table.insert(palette, b.name)
+            lookup[b.name] = #palette - 1
+        end
+        table.insert(blocks, { b.x, b.y, b.z, lookup[b.name] })
+    end
+
+    local body = JSON:encode({
+        palette  = palette,
+        blocks   = blocks,
+        relative = true,
+        origin   = Pos,
+        radius   = radius,
+    })
+    local headers = AuthHeaders("POST", "/scan", body, { ["Content-Type"] = "application/json" })
+    local response = http.post("http://localhost:3001/scan", body, headers)
+    if response then
+        response.close()
+        return true
+    end
+    print("Failed to upload scan.")
+    return false
+end
+
+function PostInfo(i, moved) -- reports what's around us; i acks step i, or moved says how far into it we are
     -- Make sure our position table (Pos) is up‑to‑date
     -- UpdatePos()
 
@@ -118,26 +250,36 @@ function PostInfo(i)
     }
 
     -- Assemble everything the backend cares about
-    local body = JSON:encode({
+    local status = {
         -- turtle_id = computer.getLabel(),
         position  = Pos,               -- {x = …, y = …, z = …}
         rotation  = Rotation,          -- 0 = N, 1 = E, 2 = S, 3 = W
+        world     = World,             -- {server = …, dimension = …}
         fuel      = turtle.getFuelLevel(),
         blocks    = visible,
-        -- instruction_index = i or 0,
-    })
-
-    local headers = {
-        ["Content-Type"] = "application/json",
-        ["turtle_id"]    = computer.getLabel()
-        ["authorization"] = "blah"
+        batch     = i and Instructions.id,
+        instruction_index = i,
+        moved     = moved,
     }
 
+    if Socket then
+        status.type = "status"
+        SendMessage(status)
+        return true
+    end
+
+    local body = JSON:encode(status)
+    local headers = AuthHeaders("POST", "/update-block", body, { ["Content-Type"] = "application/json" })
+
     local response, err, resp = http.post("http://localhost:3001/update-block", body, headers)
 
     if response then
         response.close()
         return true
+    elseif resp and resp.getResponseCode() == 409 then
+        -- Our batch was dropped by the server (stop, recall or cancel)
+        StopRequested = true
+        return false
     else
         print("Failed to post turtle info to server.")
         print(err)
@@ -148,25 +290,105 @@ function PostInfo(i)
 end
 
 
+-- Websocket connection to the server. While it's open, instructions are
+-- pushed to us and status reports go over it instead of HTTP.
+Socket = nil
+Seq = 0
+Unacked = {} -- seq -> message, resent after reconnecting
+StopRequested = false
+Finished = {} -- batch ids we've run, whether or not they got to the end
+
+function Connect()
+    local ws, err = http.websocket("ws://localhost:3001/ws", AuthHeaders("GET", "/ws"))
+    if not ws then
+        print("Websocket failed: " .. tostring(err))
+        return false
+    end
+    Socket = ws
+    for _, msg in pairs(Unacked) do
+        Socket.send(JSON:encode(msg))
+    end
+    return true
+end
+
+function SendMessage(msg)
+    Seq = Seq + 1
+    msg.seq = Seq
+    Unacked[Seq] = msg
+    if Socket then
+        Socket.send(JSON:encode(msg))
+    end
+end
+
+function Listen() -- handles everything the server pushes, reconnecting as needed
+    while true do
+        if not Socket and not Connect() then
+            sleep(5)
+        else
+            local text = Socket.receive()
+            if not text then
+                print("Websocket closed, reconnecting")
+                Socket = nil
+            else
+                local msg = JSON:decode(text)
+                if msg.type == "ack" then
+                    Unacked[msg.seq] = nil
+                elseif msg.type == "instructions" then
+                    os.queueEvent("instructions", msg)
+                elseif msg.type == "stop" or msg.type == "cancel" then
+                    StopRequested = true
+                elseif msg.type == "error" then
+                    print("Server error: " .. msg.text)
+                end
+            end
+        end
+    end
+end
+
+function Work() -- runs pushed batches one at a time, asking for more when done
+    sleep(1)
+    SendMessage({ type = "request" })
+    while true do
+        local _, batch = os.pullEvent("instructions")
+        -- A batch can arrive twice, pushed and as the answer to a request
+        if not Finished[batch.id] then
+            StopRequested = false
+            Instructions = batch
+            RunInstructions()
+            Finished[batch.id] = true
+        end
+        if #batch.steps == 0 then
+            sleep(5) -- nothing to do, e.g. while halted
+        end
+        SendMessage({ type = "request" })
+    end
+end
+
 function RunInstructions()
     for i, instruction in ipairs(Instructions["steps"]) do
-        print("Executing instruction " .. i .. ": " .. instruction)
-        if instruction == "up" then
-            MoveUp()
-        elseif instruction == "down" then
-            MoveDown()
-        elseif instruction == "north" then
-            Face(0)
-            MoveForward()
-        elseif instruction == "east" then
-            Face(1)
-            MoveForward()
-        elseif instruction == "south" then
-            Face(2)
-            MoveForward()
-        elseif instruction == "west" then
-            Face(3)
-            MoveForward()
+        if StopRequested then
+            print("Stopped by server")
+            StopRequested = false
+            break
+        end
+        local seq = (Instructions.start or 0) + i - 1
+        print("Executing instruction " .. seq .. ": " .. instruction)
+        -- Moves may come run-length encoded, e.g. "forward 37"
+        local word, count = string.match(instruction, "^(%a+) (%d+)$")
+        if not (word and MoveWords[word]) then
+            word, count = instruction, 1
+        end
+        if MoveWords[word] then
+            count = tonumber(count)
+            -- When resuming, some of the first step's moves were already made
+            local first = 1
+            if i == 1 then first = (Instructions.moved or 0) + 1 end
+            for moved = first, count do
+                Move(word)
+                if Failure or StopRequested then break end
+                -- Report each move of a run; the last one is reported below
+                if moved < count then PostInfo(seq, moved) end
+            end
         elseif instruction == "facenorth" then
             Face(0)
         elseif instruction == "faceeast" then
@@ -187,6 +409,25 @@ function RunInstructions()
             DigUp()
         elseif instruction == "digdown" then
             DigDown()
+        elseif string.sub(instruction, 1, 7) == "select " then
+            SelectItem(string.sub(instruction, 8))
+        elseif instruction == "place" then
+            turtle.place()
+        elseif instruction == "placeup" then
+            turtle.placeUp()
+        elseif instruction == "placedown" then
+            turtle.placeDown()
+        elseif string.sub(instruction, 1, 5) == "scan " then
+            Scan(tonumber(string.sub(instruction, 6)))
+        elseif instruction == "restock" then
+            while turtle.suckDown() do end
+        elseif string.sub(instruction, 1, 5) == "fell " then
+            Fell(string.sub(instruction, 6))
+        elseif string.sub(instruction, 1, 12) == "harvestdown " then
+            HarvestDown(string.sub(instruction, 13))
+        elseif string.sub(instruction, 1, 8) == "deposit " then
+            Deposit(string.sub(instruction, 9))
+            PostInventory()
         elseif instruction == "reportarea" then
             PostInfo()
             Turn(1)
@@ -199,7 +440,14 @@ function RunInstructions()
         else
             print("Unknown instruction: " .. instruction)
         end
-        PostInfo(i) -- Post info after each instruction
+        if Failure then
+            print("Instruction " .. seq .. " failed: " .. Failure)
+            PostInfo()
+            ReportFailure(seq, Failure)
+            Failure = nil
+            break
+        end
+        PostInfo(seq) -- Post info after each instruction, which also acks it
         print("pos: " .. Pos["x"] .. ", " .. Pos["y"] .. ", " .. Pos["z"])
     end
 end
@@ -216,6 +464,69 @@ function DigDown()
     while turtle.digDown() do end
 end
 
+function Fell(sapling) -- cuts down the tree in front, then replants it
+    local ok, data = turtle.inspect()
+    if ok and string.find(data.name, "log") then
+        Dig()
+        MoveForward()
+        local height = 0
+        while true do
+            local up, above = turtle.inspectUp()
+            if not (up and string.find(above.name, "log")) then break end
+            DigUp()
+            MoveUp()
+            height = height + 1
+        end
+        for _ = 1, height do MoveDown() end
+        MoveBack()
+        ok = false
+    end
+    if not ok and SelectItem(sapling) then
+        turtle.place()
+    end
+end
+
+-- Crops are fully grown at age 7, except these
+MaxAge = {
+    ["minecraft:beetroots"] = 3,
+    ["minecraft:nether_wart"] = 3,
+}
+
+function HarvestDown(seed) -- harvests a grown crop below, then replants it
+    local ok, data = turtle.inspectDown()
+    if ok and data.state and data.state.age then
+        if data.state.age < (MaxAge[data.name] or 7) then return end
+        DigDown()
+        ok = false
+    end
+    if not ok and SelectItem(seed) then
+        turtle.placeDown()
+    end
+end
+
+function Deposit(keep) -- empties everything into the chest below except fuel and `keep`
+    for slot = 1, 16 do
+        local detail = turtle.getItemDetail(slot)
+        if detail and detail.name ~= keep and detail.name ~= Fuel then
+            turtle.select(slot)
+            turtle.dropDown()
+        end
+    end
+    turtle.select(1)
+end
+
+function SelectItem(name) -- selects the first slot holding the named item
+    for slot = 1, 16 do
+        local detail = turtle.getItemDetail(slot)
+        if detail and detail.name == name then
+            turtle.select(slot)
+            return true
+        end
+    end
+    print("Out of " .. name)
+    return false
+end
+
 function UpdatePos()
     local x, y, z = gps.locate()
     if x then
@@ -302,12 +613,25 @@ function table.shallow_copy(t)
     return t2
 end
 
+MoveWords = { up = true, down = true, forward = true, north = 0, east = 1, south = 2, west = 3 }
+
+function Move(word) -- runs a single up/down/forward/north/east/south/west step
+    if word == "up" then
+        MoveUp()
+    elseif word == "down" then
+        MoveDown()
+    elseif word == "forward" then
+        MoveForward()
+    else
+        Face(MoveWords[word])
+        MoveForward()
+    end
+end
+
 function MoveForward() -- moves bot forward 1 block and updates position
     Refuel()
     if not turtle.forward() then
-        Instructions = {}
-        PostInfo()
-        GetInstructions()
+        Failure = "movement blocked"
         return
     end
     if Rotation == 0 then Pos["z"]=Pos["z"]-1 end
@@ -319,9 +643,7 @@ end
 function MoveBack() -- moves bot back 1 block and updates position
     Refuel()
     if not turtle.back() then
-        Instructions = {}
-        PostInfo()
-        GetInstructions()
+        Failure = "movement blocked"
         return
     end
     if Rotation == 0 then Pos["z"]=Pos["z"]+1 end
@@ -333,9 +655,7 @@ end
 function MoveUp() -- moves bot up 1 block and updates position
     Refuel()
     if not turtle.up() then
-        Instructions = {}
-        PostInfo()
-        GetInstructions()
+        Failure = "movement blocked"
         return
     end
     Pos["y"] = Pos["y"] + 1
@@ -344,9 +664,7 @@ end
 function MoveDown() -- moves bot down 1 block and updates position
     Refuel()
     if not turtle.down() then
-        Instructions = {}
-        PostInfo()
-        GetInstructions()
+        Failure = "movement blocked"
         return
     end
     Pos["y"] = Pos["y"] - 1
@@ -378,10 +696,17 @@ function Turn(num) -- turns bot either left (-1) or right (+1) depending on inpu
 end
 
 local function main()
+    if not LoadToken() then
+        return
+    end
     -- Refuel()
     UpdatePos()
     DetermineOrientation()
-    GetInstructions()
+    PostInventory()
+    -- Finish whatever we were doing before a reboot first
+    if not PollInstructions() then
+        GetInstructions()
+    end
     -- print("Current orientation: " .. Rotation)
 
     -- local body = textutils.serializeJSON({
@@ -433,3 +758,6 @@ end
 
 -- testing mode
 main()
+
+-- websocket mode
+-- if LoadToken() then parallel.waitForAny(Listen, Work) end
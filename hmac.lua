@@ -0,0 +1,90 @@
+-- SHA-256 and HMAC-SHA256, for signing requests (see `authenticate` in src/auth.rs)
+local band, bnot, bxor = bit32.band, bit32.bnot, bit32.bxor
+local rrotate, rshift = bit32.rrotate, bit32.rshift
+
+local K = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+}
+
+local function block(msg, i, H)
+    local w = {}
+    for j = 1, 16 do
+        local a, b, c, d = string.byte(msg, i + (j - 1) * 4, i + (j - 1) * 4 + 3)
+        w[j] = ((a * 256 + b) * 256 + c) * 256 + d
+    end
+    for j = 17, 64 do
+        local v = w[j - 15]
+        local s0 = bxor(rrotate(v, 7), rrotate(v, 18), rshift(v, 3))
+        v = w[j - 2]
+        local s1 = bxor(rrotate(v, 17), rrotate(v, 19), rshift(v, 10))
+        w[j] = band(w[j - 16] + s0 + w[j - 7] + s1, 0xffffffff)
+    end
+
+    local a, b, c, d, e, f, g, h = H[1], H[2], H[3], H[4], H[5], H[6], H[7], H[8]
+    for j = 1, 64 do
+        local s1 = bxor(rrotate(e, 6), rrotate(e, 11), rrotate(e, 25))
+        local ch = bxor(band(e, f), band(bnot(e), g))
+        local t1 = h + s1 + ch + K[j] + w[j]
+        local s0 = bxor(rrotate(a, 2), rrotate(a, 13), rrotate(a, 22))
+        local maj = bxor(band(a, b), band(a, c), band(b, c))
+        local t2 = s0 + maj
+        h, g, f, e = g, f, e, band(d + t1, 0xffffffff)
+        d, c, b, a = c, b, a, band(t1 + t2, 0xffffffff)
+    end
+    H[1] = band(H[1] + a, 0xffffffff)
+    H[2] = band(H[2] + b, 0xffffffff)
+    H[3] = band(H[3] + c, 0xffffffff)
+    H[4] = band(H[4] + d, 0xffffffff)
+    H[5] = band(H[5] + e, 0xffffffff)
+    H[6] = band(H[6] + f, 0xffffffff)
+    H[7] = band(H[7] + g, 0xffffffff)
+    H[8] = band(H[8] + h, 0xffffffff)
+end
+
+local function sha256(msg) -- returns the raw 32 byte digest
+    local len = #msg
+    -- Pad with 0x80, zeros, then the length in bits as 8 big-endian bytes
+    local bits, tail = len * 8, ""
+    for _ = 1, 8 do
+        tail = string.char(bits % 256) .. tail
+        bits = math.floor(bits / 256)
+    end
+    msg = msg .. "\128" .. string.rep("\0", (55 - len) % 64) .. tail
+
+    local H = {
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+        0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    }
+    for i = 1, #msg, 64 do
+        block(msg, i, H)
+    end
+    local out = ""
+    for i = 1, 8 do
+        local v = H[i]
+        out = out .. string.char(rshift(v, 24), band(rshift(v, 16), 255), band(rshift(v, 8), 255), band(v, 255))
+    end
+    return out
+end
+
+local function hex(s)
+    return (string.gsub(s, ".", function(c) return string.format("%02x", string.byte(c)) end))
+end
+
+local function hmac(key, msg) -- returns the raw 32 byte MAC
+    if #key > 64 then
+        key = sha256(key)
+    end
+    key = key .. string.rep("\0", 64 - #key)
+    local ipad = string.gsub(key, ".", function(c) return string.char(bxor(string.byte(c), 0x36)) end)
+    local opad = string.gsub(key, ".", function(c) return string.char(bxor(string.byte(c), 0x5c)) end)
+    return sha256(opad .. sha256(ipad .. msg))
+end
+
+return { sha256 = sha256, hmac = hmac, hex = hex }
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::auth::Identity;
+use crate::job::JobId;
+use crate::pathfinder::Point3D;
+use crate::plan;
+use crate::region;
+use crate::turtle::{Block, WorldId};
+
+// A batch's block reports are logged once it's over, or after this long.
+const MAX_BATCH_OPEN: Duration = Duration::from_secs(300);
+
+/// Something that changed the server's state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Blocks a turtle reported while running `batch`, or outside of one.
+    /// `digest` is the hex SHA-256 of the blocks in the order they were
+    /// reported, each as `x,y,z block_type` and a newline.
+    BlocksReported {
+        count: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        batch: Option<u64>,
+        #[serde(default)]
+        digest: String,
+    },
+    ScanApplied {
+        count: usize,
+    },
+    /// `slots` is how many slots have something in them.
+    InventoryUpdated {
+        slots: usize,
+    },
+    StepFailed {
+        batch: u64,
+        step: u32,
+        reason: String,
+    },
+    JobCreated {
+        job: JobId,
+    },
+    JobCancelled {
+        job: JobId,
+    },
+    TurtlesStopped {
+        turtles: Vec<u32>,
+    },
+    TurtlesRecalled {
+        turtles: Vec<u32>,
+    },
+    TurtlesReleased {
+        turtles: Vec<u32>,
+    },
+    TokenIssued {
+        turtle: u32,
+    },
+    TokenRevoked {
+        turtle: u32,
+    },
+    WorldRestored {
+        blocks: usize,
+    },
+    ConfigReloaded {
+        needs_restart: Vec<String>,
+    },
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix seconds.
+    pub time: u64,
+    /// Who did it, as `turtle 3` or `user alice`.
+    pub identity: String,
+    #[serde(flatten)]
+    pub action: Action,
+    /// The world it happened in, if it was in one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world: Option<WorldId>,
+    /// Smallest and largest corner of the blocks it touched, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<[Point3D; 2]>,
+}
+
+/// Filters for `GET /audit`; every one that's set must match.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub identity: Option<String>,
+    /// `server/dimension`, as in `WorldId`'s display.
+    pub world: Option<String>,
+    /// `x1,y1,z1,x2,y2,z2`: entries whose region overlaps this box.
+    pub region: Option<String>,
+    /// Return at most this many of the latest matches.
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn region(&self) -> Result<Option<(Point3D, Point3D)>, String> {
+        self.region.as_deref().map(region::parse_region).transpose()
+    }
+}
+
+/// Blocks reported during a turtle's batch that aren't logged yet.
+struct Reported {
+    batch: Option<u64>,
+    world: WorldId,
+    count: usize,
+    region: Option<[Point3D; 2]>,
+    digest: Sha256,
+    since: Instant,
+}
+
+/// Append-only log of state changes, one JSON entry per line.
+pub struct AuditLog {
+    path: String,
+    file: File,
+    reported: HashMap<u32, Reported>,
+}
+
+impl AuditLog {
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            path: path.to_string(),
+            file,
+            reported: HashMap::new(),
+        })
+    }
+
+    /// Note blocks a turtle reported. Reports made during one batch are
+    /// logged together once the turtle moves on to another batch, or
+    /// `end_batch` is called; the rest are logged right away.
+    pub fn blocks_reported(
+        &mut self,
+        turtle: u32,
+        batch: Option<u64>,
+        world: &WorldId,
+        blocks: &[Block],
+    ) {
+        let stale: Vec<u32> = self
+            .reported
+            .iter()
+            .filter(|(id, r)| {
+                (**id == turtle && (r.batch != batch || r.world != *world))
+                    || r.since.elapsed() > MAX_BATCH_OPEN
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in stale {
+            self.end_batch(id);
+        }
+        if blocks.is_empty() {
+            return;
+        }
+        let reported = self.reported.entry(turtle).or_insert_with(|| Reported {
+            batch,
+            world: world.clone(),
+            count: 0,
+            region: None,
+            digest: Sha256::new(),
+            since: Instant::now(),
+        });
+        reported.count += blocks.len();
+        reported.region = region_of(
+            reported
+                .region
+                .into_iter()
+                .flatten()
+                .chain(blocks.iter().map(Block::position)),
+        );
+        for block in blocks {
+            let p = block.position();
+            reported
+                .digest
+                .update(format!("{},{},{} {}\n", p.x, p.y, p.z, block.block_type()));
+        }
+        if batch.is_none() {
+            self.end_batch(turtle);
+        }
+    }
+
+    /// Log the blocks the turtle reported during its current batch.
+    pub fn end_batch(&mut self, turtle: u32) {
+        let Some(reported) = self.reported.remove(&turtle) else {
+            return;
+        };
+        let digest = reported
+            .digest
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.record(
+            &Identity::Turtle(turtle),
+            Action::BlocksReported {
+                count: reported.count,
+                batch: reported.batch,
+                digest,
+            },
+            Some(&reported.world),
+            reported.region,
+        );
+    }
+
+    pub fn record(
+        &mut self,
+        identity: &Identity,
+        action: Action,
+        world: Option<&WorldId>,
+        region: Option<[Point3D; 2]>,
+    ) {
+        let entry = Entry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            identity: identity.to_string(),
+            action,
+            world: world.cloned(),
+            region,
+        };
+        let mut line = serde_json::to_string(&entry).expect("Audit entries always serialize");
+        line.push('\n');
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            warn!("Failed to write audit log: {}", e);
+        }
+    }
+
+    /// Open the log for `search`. This doesn't read it, so it's quick enough
+    /// to do under the lock.
+    pub fn reader(&self) -> Result<File, String> {
+        File::open(&self.path).map_err(|e| e.to_string())
+    }
+}
+
+/// Entries of the log in `file` matching `query`, oldest first. This reads
+/// the whole file, so it shouldn't run on the async runtime.
+pub fn search(file: File, query: &AuditQuery) -> Result<Vec<Entry>, String> {
+    let region = query.region()?;
+    let mut matches = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        // Skip anything unreadable, e.g. a line cut short by a crash
+        let Ok(entry) = serde_json::from_str::<Entry>(&line) else {
+            continue;
+        };
+        if query.since.is_some_and(|t| entry.time < t)
+            || query.until.is_some_and(|t| entry.time > t)
+            || query
+                .identity
+                .as_ref()
+                .is_some_and(|i| *i != entry.identity)
+            || query.world.as_ref().is_some_and(|w| {
+                entry
+                    .world
+                    .as_ref()
+                    .is_none_or(|world| world.to_string() != *w)
+            })
+        {
+            continue;
+        }
+        if let Some((min, max)) = region {
+            let Some([lo, hi]) = entry.region else {
+                continue;
+            };
+            if lo.x > max.x
+                || lo.y > max.y
+                || lo.z > max.z
+                || hi.x < min.x
+                || hi.y < min.y
+                || hi.z < min.z
+            {
+                continue;
+            }
+        }
+        matches.push(entry);
+    }
+    if let Some(limit) = query.limit {
+        matches.drain(..matches.len().saturating_sub(limit));
+    }
+    Ok(matches)
+}
+
+/// Smallest box holding every point, for an entry's `region`.
+pub fn region_of(points: impl IntoIterator<Item = Point3D>) -> Option<[Point3D; 2]> {
+    points.into_iter().fold(None, |region, p| match region {
+        None => Some([p, p]),
+        Some([lo, hi]) => {
+            let (lo, _) = plan::bounds(lo, p);
+            let (_, hi) = plan::bounds(hi, p);
+            Some([lo, hi])
+        }
+    })
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{StatusCode, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Message;
+use crate::config::Config;
+use crate::state::AppState;
+
+// Signed requests older or newer than this are rejected, and nonces are
+// remembered for as long.
+const MAX_CLOCK_SKEW: u64 = 60;
+// Scan uploads are the biggest bodies turtles send, and `/scan` takes up to
+// the same.
+pub const MAX_SIGNED_BODY: usize = 16 * 1024 * 1024;
+
+/// Hex SHA-256 of a secret. Only these are ever stored, both here and in
+/// `config.toml`.
+pub fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    // from_str_radix would also take a sign, as in "+f"
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn registration_is_valid(config: &Config, key: &str) -> bool {
+    config.registration_key_sha256 == hash(key)
+}
+
+/// Hashed tokens issued to turtles, keyed by turtle id.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Credentials {
+    tokens: HashMap<u32, String>,
+}
+
+impl Credentials {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, turtle_id: u32) -> bool {
+        self.tokens.contains_key(&turtle_id)
+    }
+
+    /// Make a new token for a turtle. The caller hands it out; only its hash
+    /// is kept.
+    pub fn issue(&mut self, turtle_id: u32) -> String {
+        let mut bytes = [0u8; 32];
+        rand::rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        self.tokens.insert(turtle_id, hash(&token));
+        token
+    }
+
+    pub fn revoke(&mut self, turtle_id: u32) -> bool {
+        self.tokens.remove(&turtle_id).is_some()
+    }
+
+    pub fn verify(&self, turtle_id: u32, token: &str) -> bool {
+        self.tokens.get(&turtle_id) == Some(&hash(token))
+    }
+
+    /// The key a turtle signs requests with, which is the hash of its token.
+    /// It's the stored hash itself, so anyone with the file can sign.
+    fn signing_key(&self, turtle_id: u32) -> Option<Vec<u8>> {
+        from_hex(self.tokens.get(&turtle_id)?)
+    }
+}
+
+/// Nonces of recent signed requests, with their timestamps.
+#[derive(Default)]
+pub struct Nonces {
+    seen: HashMap<String, u64>,
+}
+
+impl Nonces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a nonce. Returns false if it was already used.
+    fn insert(&mut self, nonce: &str, timestamp: u64, now: u64) -> bool {
+        // Anything older would fail the timestamp check anyway
+        self.seen.retain(|_, t| *t + MAX_CLOCK_SKEW >= now);
+        self.seen.insert(nonce.to_string(), timestamp).is_none()
+    }
+}
+
+/// What a user may do. Each role can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// See the map, fleet and jobs.
+    Viewer,
+    /// Also create and cancel jobs and command the fleet.
+    Operator,
+    /// Also manage turtle credentials.
+    Admin,
+}
+
+/// A person or tool using the API, listed under `[[users]]` in `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub key_sha256: String,
+}
+
+/// Who a request comes from, as worked out by `authenticate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    User {
+        name: String,
+        role: Role,
+    },
+    Turtle(u32),
+    /// The server itself, e.g. reloading its config when the file changes.
+    /// Only used in the audit log; no request has it.
+    System,
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Identity::User { name, .. } => write!(f, "user {}", name),
+            Identity::Turtle(id) => write!(f, "turtle {}", id),
+            Identity::System => write!(f, "system"),
+        }
+    }
+}
+
+/// Who may use a group of routes, checked by `require`.
+#[derive(Debug, Clone, Copy)]
+pub enum Access {
+    Turtles,
+    Users(Role),
+    TurtlesOrUsers(Role),
+}
+
+impl Access {
+    fn allows(&self, identity: &Identity) -> bool {
+        match (self, identity) {
+            (Access::Turtles | Access::TurtlesOrUsers(_), Identity::Turtle(_)) => true,
+            (Access::Users(min) | Access::TurtlesOrUsers(min), Identity::User { role, .. }) => {
+                role >= min
+            }
+            _ => false,
+        }
+    }
+
+    fn denied(&self) -> &'static str {
+        match self {
+            Access::Turtles => "Turtles only",
+            Access::Users(Role::Viewer) | Access::TurtlesOrUsers(Role::Viewer) => {
+                "Requires the viewer role"
+            }
+            Access::Users(Role::Operator) | Access::TurtlesOrUsers(Role::Operator) => {
+                "Requires the operator role"
+            }
+            Access::Users(Role::Admin) | Access::TurtlesOrUsers(Role::Admin) => {
+                "Requires the admin role"
+            }
+        }
+    }
+}
+
+/// Why a request was turned away: no valid credentials (401), or credentials
+/// without the access the route needs (403).
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthenticated(&'static str),
+    Forbidden(&'static str),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, text) = match self {
+            AuthError::Unauthenticated(text) => (StatusCode::UNAUTHORIZED, text),
+            AuthError::Forbidden(text) => (StatusCode::FORBIDDEN, text),
+        };
+        (
+            status,
+            Json(Message {
+                text: text.to_string(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Route layer that turns away anyone `access` doesn't cover. Runs after
+/// `authenticate`, so every route in the `Router` sits behind one of these
+/// unless it's public.
+pub async fn require(access: Access, req: Request, next: Next) -> Response {
+    match req.extensions().get::<Identity>() {
+        None => AuthError::Unauthenticated("Missing or invalid credentials").into_response(),
+        Some(identity) if !access.allows(identity) => {
+            AuthError::Forbidden(access.denied()).into_response()
+        }
+        Some(_) => next.run(req).await,
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Identity {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, AuthError> {
+        parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .ok_or(AuthError::Unauthenticated("Missing or invalid credentials"))
+    }
+}
+
+/// Extracts the id of the turtle making the request, only for turtles.
+pub struct TurtleId(pub u32);
+
+impl<S: Send + Sync> FromRequestParts<S> for TurtleId {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AuthError> {
+        match Identity::from_request_parts(parts, state).await? {
+            Identity::Turtle(id) => Ok(TurtleId(id)),
+            Identity::User { .. } | Identity::System => Err(AuthError::Forbidden("Turtles only")),
+        }
+    }
+}
+
+fn unauthorized(text: &'static str) -> Response {
+    AuthError::Unauthenticated(text).into_response()
+}
+
+/// Work out who a request comes from and attach it as an `Identity` for
+/// `require` and the extractors above. Requests with a `turtle-id` header are
+/// from that turtle, anything else is from one of the configured users.
+///
+/// Signed requests carry `x-signature`, a hex HMAC-SHA256 over
+/// `"{method}\n{path}\n{x-timestamp}\n{x-nonce}\n{body}"`. Turtles sign with the
+/// hash of their token, users with the hash of their key and name themselves
+/// in `x-user`. Those hashes are what the server stores, so signing keeps
+/// secrets off the wire but doesn't help if the server's files leak, which is
+/// why they're refused unless `require_signatures` is set. Otherwise the raw
+/// token or key goes in `Authorization`, which is ignored if it is. Requests without valid credentials get no
+/// identity, and are only let through to routes that don't need one.
+pub async fn authenticate(State(st): State<AppState>, mut req: Request, next: Next) -> Response {
+    // Owned, since the request isn't Sync and can't be borrowed across awaits
+    let headers = req.headers().clone();
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+    let turtle_id = match header("turtle-id").map(str::parse::<u32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: "Invalid turtle-id header".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        None => None,
+    };
+    let config = st.config().await;
+    let Some(signature) = header("x-signature") else {
+        // Registering can't be signed, so unsigned requests still go through,
+        // just without an identity
+        if !config.require_signatures
+            && let Some(key) = header("Authorization")
+        {
+            let identity = match turtle_id {
+                Some(id) => st
+                    .credentials
+                    .read()
+                    .await
+                    .verify(id, key)
+                    .then_some(Identity::Turtle(id)),
+                None => {
+                    // Scrapers like Prometheus send keys as bearer tokens
+                    let hashed = hash(key.strip_prefix("Bearer ").unwrap_or(key));
+                    config
+                        .users
+                        .iter()
+                        .find(|u| u.key_sha256 == hashed)
+                        .map(|u| Identity::User {
+                            name: u.name.clone(),
+                            role: u.role,
+                        })
+                }
+            };
+            if let Some(identity) = identity {
+                req.extensions_mut().insert(identity);
+            }
+        }
+        return next.run(req).await;
+    };
+    // The stored hashes can't be used as tokens, so leaked files only let
+    // someone in if signatures are accepted
+    if !config.require_signatures {
+        return unauthorized("Signed requests need require_signatures");
+    }
+    let (Some(timestamp), Some(nonce)) = (
+        header("x-timestamp").and_then(|t| t.parse::<u64>().ok()),
+        header("x-nonce"),
+    ) else {
+        return unauthorized("Signed requests need x-timestamp and x-nonce");
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW {
+        return unauthorized("Stale timestamp");
+    }
+
+    let signer = match (turtle_id, header("x-user")) {
+        (Some(id), _) => st
+            .credentials
+            .read()
+            .await
+            .signing_key(id)
+            .map(|key| (Identity::Turtle(id), key)),
+        (None, Some(name)) => config.users.iter().find(|u| u.name == name).and_then(|u| {
+            let identity = Identity::User {
+                name: u.name.clone(),
+                role: u.role,
+            };
+            Some((identity, from_hex(&u.key_sha256)?))
+        }),
+        (None, None) => None,
+    };
+    let Some((identity, key)) = signer else {
+        return unauthorized("Unknown signer");
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let Ok(body) = to_bytes(body, MAX_SIGNED_BODY).await else {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(Message {
+                text: "Body too large to verify".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any size");
+    mac.update(format!("{}\n{}\n{}\n{}\n", parts.method, path, timestamp, nonce).as_bytes());
+    mac.update(&body);
+    let valid = from_hex(signature).is_some_and(|sig| mac.verify_slice(&sig).is_ok());
+    if !valid {
+        return unauthorized("Invalid signature");
+    }
+    // Only checked once the signature holds, so forged requests can't use up nonces
+    if !st.nonces.write().await.insert(nonce, timestamp, now) {
+        return unauthorized("Nonce was already used");
+    }
+
+    parts.extensions.insert(identity);
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_takes_only_hex_digits() {
+        assert_eq!(from_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        assert_eq!(from_hex("+f"), None);
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("abc"), None);
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::audit::Action;
+use crate::auth::{Identity, User};
+use crate::events::EventSettings;
+use crate::limit::Limits;
+use crate::render::{self, Render};
+use crate::state::AppState;
+
+// How often `watch` checks the config file for changes.
+const WATCH_EVERY: Duration = Duration::from_secs(2);
+
+/// Command line flags. Each can also be set through the environment variable
+/// next to it, and both win over `config.toml`.
+#[derive(Debug, Parser)]
+#[command(version, about = "Turtle manager server")]
+pub struct Args {
+    /// Config file to load.
+    #[arg(long, env = "TURTLE_CONFIG", default_value = "config.toml")]
+    pub config: PathBuf,
+    #[arg(long, env = "TURTLE_BIND")]
+    pub bind: Option<IpAddr>,
+    #[arg(long, env = "TURTLE_PORT")]
+    pub port: Option<u16>,
+    /// Where the block caches are saved, one file per world.
+    #[arg(long, env = "TURTLE_WORLDS_DIR")]
+    pub worlds_dir: Option<String>,
+    #[arg(long, env = "TURTLE_SAVE_EVERY_SECS")]
+    pub save_every_secs: Option<u64>,
+    #[arg(long, env = "TURTLE_REQUIRE_SIGNATURES")]
+    pub require_signatures: Option<bool>,
+    /// Whether paths may dig through blocks.
+    #[arg(long, env = "TURTLE_CAN_DIG")]
+    pub can_dig: Option<bool>,
+    /// One of off, error, warn, info, debug or trace.
+    #[arg(long, env = "TURTLE_LOG_LEVEL")]
+    pub log_level: Option<String>,
+}
+
+// Keys are stored as hex SHA-256, see `auth::hash`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub registration_key_sha256: String,
+    /// Reject requests that aren't signed, see `auth::authenticate`.
+    #[serde(default)]
+    pub require_signatures: bool,
+    /// Everyone who uses the API, each with their own key and role.
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub server: Server,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub world: WorldLimits,
+    #[serde(default)]
+    pub pathfinder: Pathfinder,
+    #[serde(default)]
+    pub query: Query,
+    #[serde(default)]
+    pub render: Render,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub events: EventSettings,
+    #[serde(default)]
+    pub heartbeat: Heartbeat,
+    #[serde(default)]
+    pub logging: Logging,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    pub bind: IpAddr,
+    pub port: u16,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3001,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storage {
+    /// Each world is saved to `{worlds}/{server}/{dimension}.bin`.
+    pub worlds: String,
+    pub credentials: String,
+    pub audit: String,
+    /// Build jobs can only use schematic files under this directory.
+    pub schematics: String,
+    /// How often the world is saved.
+    pub save_every_secs: u64,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            worlds: "data/worlds".to_string(),
+            credentials: "data/credentials.json".to_string(),
+            audit: "data/audit.jsonl".to_string(),
+            schematics: "schematics".to_string(),
+            save_every_secs: 120,
+        }
+    }
+}
+
+/// Build height limits; path goals outside them are clamped.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeightLimits {
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+/// Height limits for dimensions listed under `[world.dimensions]`, and the
+/// ones to use for any other dimension.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldLimits {
+    pub min_y: i32,
+    pub max_y: i32,
+    pub dimensions: BTreeMap<String, HeightLimits>,
+}
+
+impl Default for WorldLimits {
+    fn default() -> Self {
+        WorldLimits {
+            min_y: -60,
+            max_y: 318,
+            dimensions: BTreeMap::from([
+                (
+                    "minecraft:the_nether".to_string(),
+                    HeightLimits {
+                        min_y: 0,
+                        max_y: 127,
+                    },
+                ),
+                (
+                    "minecraft:the_end".to_string(),
+                    HeightLimits {
+                        min_y: 0,
+                        max_y: 255,
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+impl WorldLimits {
+    pub fn height(&self, dimension: &str) -> HeightLimits {
+        self.dimensions
+            .get(dimension)
+            .copied()
+            .unwrap_or(HeightLimits {
+                min_y: self.min_y,
+                max_y: self.max_y,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pathfinder {
+    /// Blocks added around the start and goal when building the search grid.
+    pub padding: u32,
+    /// Let paths go through blocks other than bedrock by digging them.
+    pub can_dig: bool,
+    /// Searches needing a bigger grid than this are refused. Each cell costs
+    /// about 10 bytes while the search runs.
+    pub max_grid_cells: usize,
+}
+
+impl Default for Pathfinder {
+    fn default() -> Self {
+        Pathfinder {
+            padding: 2,
+            can_dig: true,
+            max_grid_cells: 4_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Query {
+    /// Region queries over boxes bigger than this are refused.
+    pub max_cells: u64,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            max_cells: 4_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Heartbeat {
+    /// Turtles silent for this long aren't given new jobs and are listed as
+    /// offline.
+    pub offline_after_secs: u64,
+    /// Sockets that receive nothing for this long are closed.
+    pub socket_idle_secs: u64,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            offline_after_secs: 60,
+            socket_idle_secs: 120,
+        }
+    }
+}
+
+impl Heartbeat {
+    pub fn offline_after(&self) -> Duration {
+        Duration::from_secs(self.offline_after_secs)
+    }
+
+    pub fn socket_idle(&self) -> Duration {
+        Duration::from_secs(self.socket_idle_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    /// One of off, error, warn, info, debug or trace.
+    pub level: String,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Logging {
+    pub fn level(&self) -> LevelFilter {
+        self.level.parse().unwrap_or(LevelFilter::INFO)
+    }
+}
+
+impl Config {
+    /// Read the config file named in `args`, apply the overrides and check
+    /// the result.
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let path = args.config.display();
+        let text = std::fs::read_to_string(&args.config)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+        config.apply(args);
+        // Hashes are compared as lowercase hex
+        config.registration_key_sha256.make_ascii_lowercase();
+        for user in &mut config.users {
+            user.key_sha256.make_ascii_lowercase();
+        }
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config in {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, args: &Args) {
+        if let Some(bind) = args.bind {
+            self.server.bind = bind;
+        }
+        if let Some(port) = args.port {
+            self.server.port = port;
+        }
+        if let Some(dir) = &args.worlds_dir {
+            self.storage.worlds = dir.clone();
+        }
+        if let Some(secs) = args.save_every_secs {
+            self.storage.save_every_secs = secs;
+        }
+        if let Some(require) = args.require_signatures {
+            self.require_signatures = require;
+        }
+        if let Some(can_dig) = args.can_dig {
+            self.pathfinder.can_dig = can_dig;
+        }
+        if let Some(level) = &args.log_level {
+            self.logging.level = level.clone();
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_sha256(&self.registration_key_sha256) {
+            return Err("registration_key_sha256 must be 64 hex digits".to_string());
+        }
+        for (i, user) in self.users.iter().enumerate() {
+            if user.name.is_empty() {
+                return Err(format!("users[{}] has no name", i));
+            }
+            if self.users[..i].iter().any(|u| u.name == user.name) {
+                return Err(format!("user {} is listed twice", user.name));
+            }
+            if !is_sha256(&user.key_sha256) {
+                return Err(format!(
+                    "key_sha256 of user {} must be 64 hex digits",
+                    user.name
+                ));
+            }
+        }
+        if self.storage.save_every_secs == 0 {
+            return Err("storage.save_every_secs must be at least 1".to_string());
+        }
+        if self.world.min_y >= self.world.max_y {
+            return Err("world.min_y must be below world.max_y".to_string());
+        }
+        for (dimension, limits) in &self.world.dimensions {
+            if limits.min_y >= limits.max_y {
+                return Err(format!(
+                    "min_y of dimension {} must be below its max_y",
+                    dimension
+                ));
+            }
+        }
+        if self.pathfinder.max_grid_cells == 0 {
+            return Err("pathfinder.max_grid_cells must be at least 1".to_string());
+        }
+        if self.query.max_cells == 0 {
+            return Err("query.max_cells must be at least 1".to_string());
+        }
+        if self.render.scale == 0 || self.render.scale > render::MAX_SCALE {
+            return Err(format!(
+                "render.scale must be from 1 to {}",
+                render::MAX_SCALE
+            ));
+        }
+        if self.render.max_pixels == 0 {
+            return Err("render.max_pixels must be at least 1".to_string());
+        }
+        for (block, color) in &self.render.colors {
+            if render::parse_color(color).is_none() {
+                return Err(format!(
+                    "render color {:?} of {} must be #rrggbb",
+                    color, block
+                ));
+            }
+        }
+        if self.limits.requests_per_second <= 0.0
+            || self.limits.address_requests_per_second <= 0.0
+            || self.limits.path_searches_per_minute <= 0.0
+        {
+            return Err(
+                "limits.requests_per_second, address_requests_per_second and path_searches_per_minute must be above 0"
+                    .to_string(),
+            );
+        }
+        if self.limits.burst == 0
+            || self.limits.address_burst == 0
+            || self.limits.path_search_burst == 0
+        {
+            return Err(
+                "limits.burst, address_burst and path_search_burst must be at least 1".to_string(),
+            );
+        }
+        if self.limits.max_path_searches == 0 {
+            return Err("limits.max_path_searches must be at least 1".to_string());
+        }
+        if self.limits.max_scan_radius < 0 {
+            return Err("limits.max_scan_radius can't be negative".to_string());
+        }
+        if self.heartbeat.offline_after_secs == 0 || self.heartbeat.socket_idle_secs == 0 {
+            return Err("heartbeat timeouts must be at least 1 second".to_string());
+        }
+        if self.logging.level.parse::<LevelFilter>().is_err() {
+            return Err(format!(
+                "logging.level {:?} isn't one of off, error, warn, info, debug or trace",
+                self.logging.level
+            ));
+        }
+        Ok(())
+    }
+
+    /// Put back the running values of settings that are only read at
+    /// startup, returning the names of the ones that differed.
+    fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.server != running.server {
+            changed.push("server");
+            self.server = running.server.clone();
+        }
+        if self.storage != running.storage {
+            changed.push("storage");
+            self.storage = running.storage.clone();
+        }
+        if self.logging != running.logging {
+            changed.push("logging");
+            self.logging = running.logging.clone();
+        }
+        if self.limits.max_path_searches != running.limits.max_path_searches {
+            changed.push("limits.max_path_searches");
+            self.limits.max_path_searches = running.limits.max_path_searches;
+        }
+        changed
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        SocketAddr::new(self.server.bind, self.server.port)
+    }
+
+    pub fn save_every(&self) -> Duration {
+        Duration::from_secs(self.storage.save_every_secs)
+    }
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Load the config again the same way it was at startup and swap it in
+/// whole. An invalid config leaves the running one alone. Returns the changed
+/// settings that need a restart to take effect. Audited as `identity`.
+pub async fn reload(st: &AppState, identity: &Identity) -> Result<Vec<&'static str>, String> {
+    let mut config = Config::load(&st.args)?;
+    let limits = config.limits.clone();
+    let needs_restart = {
+        let mut running = st.config.write().await;
+        let needs_restart = config.keep_startup_settings(&running);
+        *running = Arc::new(config);
+        needs_restart
+    };
+    st.address_limiter
+        .write()
+        .await
+        .set_rate(limits.address_requests_per_second, limits.address_burst);
+    st.rate_limiter
+        .write()
+        .await
+        .set_rate(limits.requests_per_second, limits.burst);
+    st.path_limiter.write().await.set_rate(
+        limits.path_searches_per_minute / 60.0,
+        limits.path_search_burst,
+    );
+    info!("Reloaded config from {}", st.args.config.display());
+    if !needs_restart.is_empty() {
+        warn!("Restart to apply changes to {}", needs_restart.join(", "));
+    }
+    st.audit.write().await.record(
+        identity,
+        Action::ConfigReloaded {
+            needs_restart: needs_restart.iter().map(|s| s.to_string()).collect(),
+        },
+        None,
+        None,
+    );
+    Ok(needs_restart)
+}
+
+/// Reload whenever the config file's modification time changes.
+pub async fn watch(st: AppState) {
+    let modified = || {
+        std::fs::metadata(&st.args.config)
+            .and_then(|m| m.modified())
+            .ok()
+    };
+    let mut last = modified();
+    let mut ticker = tokio::time::interval(WATCH_EVERY);
+    loop {
+        ticker.tick().await;
+        let now = modified();
+        if now == last {
+            continue;
+        }
+        last = now;
+        if let Err(e) = reload(&st, &Identity::System).await {
+            warn!("Keeping the current config: {}", e);
+        }
+    }
+}
+
+/// Reload on SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_hangup(st: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+    while hangup.recv().await.is_some() {
+        if let Err(e) = reload(&st, &Identity::System).await {
+            warn!("Keeping the current config: {}", e);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::pathfinder::{Point3D, split_count};
+use serde::Serialize;
+
+// Moving, turning and digging each take 8 game ticks on a turtle.
+const TICKS_PER_ACTION: u32 = 8;
+const TICKS_PER_MINUTE: f32 = 20.0 * 60.0;
+// Slot 16 is kept for fuel (see `Fuel` in creep.lua).
+pub const INVENTORY_CAPACITY: u32 = 15 * 64;
+
+/// Rough cost of running an instruction stream.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Estimate {
+    pub moves: u32,
+    pub turns: u32,
+    pub digs: u32,
+    pub fuel: u32,
+    pub inventory_trips: u32,
+    pub minutes: f32,
+}
+
+impl Estimate {
+    /// Simulate `steps` from `start`, facing `rotation` (0 = N, 1 = E, 2 = S,
+    /// 3 = W), counting a dig only when it would actually remove a block.
+    /// `solid` is the world's `solid_map`; cells missing from it are assumed
+    /// to be solid.
+    pub fn from_steps(
+        steps: &[String],
+        start: Point3D,
+        rotation: u8,
+        solid: &HashMap<Point3D, bool>,
+    ) -> Self {
+        let mut dug: HashSet<Point3D> = HashSet::new();
+        let mut est = Estimate::default();
+        let mut pos = start;
+        let mut rot = rotation % 4;
+        let mut mined = 0;
+
+        let mut dig_at = |p: Point3D, est: &mut Estimate| {
+            est.digs += 1;
+            if dug.insert(p) && solid.get(&p).copied().unwrap_or(true) {
+                mined += 1;
+            }
+        };
+
+        for step in steps.iter().flat_map(|s| {
+            let (word, count) = split_count(s);
+            std::iter::repeat_n(word, count as usize)
+        }) {
+            match step {
+                "up" => {
+                    pos.y += 1;
+                    est.moves += 1;
+                }
+                "down" => {
+                    pos.y -= 1;
+                    est.moves += 1;
+                }
+                "north" | "east" | "south" | "west" => {
+                    let want = rotation_of(step).unwrap();
+                    est.turns += turns_between(rot, want);
+                    rot = want;
+                    pos = ahead(pos, rot, 1);
+                    est.moves += 1;
+                }
+                "facenorth" | "faceeast" | "facesouth" | "facewest" => {
+                    let want = rotation_of(&step[4..]).unwrap();
+                    est.turns += turns_between(rot, want);
+                    rot = want;
+                }
+                "forward" => {
+                    pos = ahead(pos, rot, 1);
+                    est.moves += 1;
+                }
+                "moveback" => {
+                    pos = ahead(pos, rot, -1);
+                    est.moves += 1;
+                }
+                "turnleft" => {
+                    rot = (rot + 3) % 4;
+                    est.turns += 1;
+                }
+                "turnright" => {
+                    rot = (rot + 1) % 4;
+                    est.turns += 1;
+                }
+                "dig" => dig_at(ahead(pos, rot, 1), &mut est),
+                "digup" => dig_at(Point3D::new(pos.x, pos.y + 1, pos.z), &mut est),
+                "digdown" => dig_at(Point3D::new(pos.x, pos.y - 1, pos.z), &mut est),
+                "reportarea" => est.turns += 4,
+                "restock" => est.inventory_trips += 1,
+                _ => {}
+            }
+        }
+
+        est.inventory_trips += mined / INVENTORY_CAPACITY;
+        est.fuel = est.moves;
+        let actions = est.moves + est.turns + est.digs;
+        est.minutes = (actions * TICKS_PER_ACTION) as f32 / TICKS_PER_MINUTE;
+        est
+    }
+}
+
+fn rotation_of(word: &str) -> Option<u8> {
+    match word {
+        "north" => Some(0),
+        "east" => Some(1),
+        "south" => Some(2),
+        "west" => Some(3),
+        _ => None,
+    }
+}
+
+fn turns_between(from: u8, to: u8) -> u32 {
+    match (to + 4 - from) % 4 {
+        0 => 0,
+        2 => 2,
+        _ => 1,
+    }
+}
+
+fn ahead(pos: Point3D, rotation: u8, dist: i32) -> Point3D {
+    match rotation {
+        0 => Point3D::new(pos.x, pos.y, pos.z - dist),
+        1 => Point3D::new(pos.x + dist, pos.y, pos.z),
+        2 => Point3D::new(pos.x, pos.y, pos.z + dist),
+        _ => Point3D::new(pos.x - dist, pos.y, pos.z),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn estimate(words: &[&str], solid: &HashMap<Point3D, bool>) -> Estimate {
+        Estimate::from_steps(&steps(words), Point3D::new(0, 0, 0), 0, solid)
+    }
+
+    #[test]
+    fn nothing_costs_nothing() {
+        let est = estimate(&[], &HashMap::new());
+        assert_eq!((est.moves, est.turns, est.digs, est.fuel), (0, 0, 0, 0));
+        assert_eq!(est.inventory_trips, 0);
+        assert_eq!(est.minutes, 0.0);
+    }
+
+    #[test]
+    fn counted_steps_match_repeated_ones() {
+        let solid = HashMap::new();
+        // Only moves take a count
+        let counted = estimate(&["faceeast", "forward 3", "up 1", "up 1", "dig"], &solid);
+        let repeated = estimate(&["east", "east", "east", "up", "up", "dig"], &solid);
+        assert_eq!(
+            (counted.moves, counted.turns, counted.digs),
+            (repeated.moves, repeated.turns, repeated.digs)
+        );
+        assert_eq!((counted.moves, counted.turns, counted.digs), (5, 1, 1));
+    }
+
+    #[test]
+    fn mixed_words() {
+        let est = estimate(
+            &[
+                "turnright",
+                "forward",
+                "turnleft",
+                "turnleft",
+                "moveback",
+                "south",
+                "down",
+                "reportarea",
+                "restock",
+                "placedown",
+            ],
+            &HashMap::new(),
+        );
+        // turnright, two turnlefts, from west to south, reportarea
+        assert_eq!(est.turns, 1 + 2 + 1 + 4);
+        assert_eq!(est.moves, 4);
+        assert_eq!(est.fuel, 4);
+        assert_eq!(est.inventory_trips, 1);
+        let actions = est.moves + est.turns + est.digs;
+        assert_eq!(
+            est.minutes,
+            (actions * TICKS_PER_ACTION) as f32 / TICKS_PER_MINUTE
+        );
+    }
+
+    #[test]
+    fn digging_air_or_twice_mines_nothing() {
+        let mut solid = HashMap::new();
+        solid.insert(Point3D::new(0, 1, 0), false);
+        // Digging air and the same block again still take the time
+        let est = estimate(&["digup", "dig", "dig"], &solid);
+        assert_eq!(est.digs, 3);
+        assert_eq!(est.inventory_trips, 0);
+    }
+
+    #[test]
+    fn full_inventory_needs_a_trip() {
+        let mut words = vec!["facesouth"];
+        for _ in 0..INVENTORY_CAPACITY {
+            words.extend(["dig", "forward"]);
+        }
+        assert_eq!(estimate(&words, &HashMap::new()).inventory_trips, 1);
+        assert_eq!(
+            estimate(&words[..words.len() - 2], &HashMap::new()).inventory_trips,
+            0
+        );
+    }
+}
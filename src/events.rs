@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse,
+        sse::{Event as SseEvent, KeepAlive, Sse},
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::Message;
+use crate::job::{JobId, JobStatus};
+use crate::pathfinder::Point3D;
+use crate::region;
+use crate::state::AppState;
+use crate::turtle::{Block, WorldId};
+
+// Events a subscriber can fall behind by before it starts missing them
+const CAPACITY: usize = 1024;
+
+// How often turtles are checked for having gone quiet
+const LOST_CHECK_EVERY: Duration = Duration::from_secs(5);
+
+/// Something that changed, as sent to `/events` subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TurtleRegistered {
+        turtle: u32,
+        world: WorldId,
+        position: Point3D,
+    },
+    TurtleMoved {
+        turtle: u32,
+        world: WorldId,
+        position: Point3D,
+        facing: u8,
+    },
+    /// The turtle's fuel dropped below `events.low_fuel`.
+    TurtleLowFuel { turtle: u32, fuel: u32 },
+    /// Nothing has been heard from the turtle for `heartbeat.offline_after_secs`.
+    TurtleLost { turtle: u32 },
+    JobChanged {
+        job: JobId,
+        status: JobStatus,
+        assigned_to: Option<u32>,
+    },
+    /// Blocks whose cached type changed.
+    BlocksChanged { world: WorldId, blocks: Vec<Block> },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::TurtleRegistered { .. } => "turtle_registered",
+            Event::TurtleMoved { .. } => "turtle_moved",
+            Event::TurtleLowFuel { .. } => "turtle_low_fuel",
+            Event::TurtleLost { .. } => "turtle_lost",
+            Event::JobChanged { .. } => "job_changed",
+            Event::BlocksChanged { .. } => "blocks_changed",
+        }
+    }
+}
+
+/// The `[events]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventSettings {
+    /// Fuel level below which `turtle_low_fuel` is sent.
+    pub low_fuel: u32,
+}
+
+impl Default for EventSettings {
+    fn default() -> Self {
+        EventSettings { low_fuel: 500 }
+    }
+}
+
+/// Broadcasts events to every `/events` subscriber. Cloning it gives
+/// another handle to the same bus.
+#[derive(Debug, Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Events { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// Sends `turtle_lost` for turtles that stop reporting.
+pub async fn watch_turtles(st: AppState) {
+    let mut interval = tokio::time::interval(LOST_CHECK_EVERY);
+    loop {
+        interval.tick().await;
+        let offline_after = st.config().await.heartbeat.offline_after();
+        st.turtles.write().await.find_lost(offline_after);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// `server/dimension` the region is in; the default world if not set.
+    world: Option<String>,
+    /// `x1,y1,z1,x2,y2,z2`: send `blocks_changed` for blocks in this box.
+    /// Without it no block changes are sent.
+    region: Option<String>,
+}
+
+/// `GET /events`: a server-sent event stream of fleet, job and block changes.
+/// Each event is named after its `type`. A `lagged` event means the
+/// subscriber fell behind and missed `missed` events.
+pub async fn stream_events(
+    State(st): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> impl IntoResponse {
+    let world = match query.world.as_deref().map(str::parse).transpose() {
+        Ok(world) => world.unwrap_or_default(),
+        Err(text) => return (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    };
+    let region = match query
+        .region
+        .as_deref()
+        .map(region::parse_region)
+        .transpose()
+    {
+        Ok(region) => region,
+        Err(text) => return (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    };
+    let subscription = Subscription {
+        receiver: st.events.subscribe(),
+        world,
+        region,
+    };
+    let stream = futures_util::stream::unfold(subscription, |mut sub| async move {
+        let event = sub.next().await?;
+        Some((event, sub))
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+struct Subscription {
+    receiver: broadcast::Receiver<Event>,
+    world: WorldId,
+    region: Option<(Point3D, Point3D)>,
+}
+
+impl Subscription {
+    /// The next event the subscriber wants, or None once the bus is gone.
+    async fn next(&mut self) -> Option<Result<SseEvent, axum::Error>> {
+        loop {
+            let event = match self.receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    return Some(
+                        SseEvent::default()
+                            .event("lagged")
+                            .json_data(serde_json::json!({ "missed": missed })),
+                    );
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            if let Some(event) = self.filter(event) {
+                return Some(SseEvent::default().event(event.name()).json_data(&event));
+            }
+        }
+    }
+
+    /// Trims block changes down to the subscribed region, dropping them if
+    /// none are left.
+    fn filter(&self, event: Event) -> Option<Event> {
+        let Event::BlocksChanged { world, blocks } = event else {
+            return Some(event);
+        };
+        let (min, max) = self.region?;
+        if world != self.world {
+            return None;
+        }
+        let blocks: Vec<Block> = blocks
+            .into_iter()
+            .filter(|b| {
+                let p = b.position();
+                p.x >= min.x
+                    && p.x <= max.x
+                    && p.y >= min.y
+                    && p.y <= max.y
+                    && p.z >= min.z
+                    && p.z <= max.z
+            })
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+        Some(Event::BlocksChanged { world, blocks })
+    }
+}
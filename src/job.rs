@@ -1,27 +1,115 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::audit;
+use crate::estimate::Estimate;
+use crate::events::{Event, Events};
 use crate::pathfinder::Point3D;
+use crate::plan;
+use crate::schematic::{self, Structure};
+use crate::turtle::{Block, World, WorldId, Worlds};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+// Failed runs a job gets before it's given up on.
+const MAX_ATTEMPTS: u32 = 3;
+// How long a failed job waits before it's handed out again, e.g. for a mob
+// in the way to wander off.
+const RETRY_AFTER: Duration = Duration::from_secs(10);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct JobId(u64);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum JobStatus {
     Pending,
     InProgress,
     Paused,
     Done,
     Failed,
+    Cancelled,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Job {
     pub id: JobId,
     pub status: JobStatus,
     pub progress: f32,
-    pub assigned_to: Option<i32>, // None = unassigned
+    pub assigned_to: Option<u32>, // None = unassigned
+    /// Only turtles in this world can take the job.
+    pub world: WorldId,
     pub kind: JobKind,
+    /// Instructions to run once the turtle has reached `path_goal`.
+    #[serde(skip)]
+    pub steps: Vec<String>,
+    pub estimate: Estimate,
+    /// Blocks the job should leave behind, checked against reports.
+    #[serde(skip)]
+    pub targets: Vec<Block>,
+    /// Items the turtle needs to carry, by name.
+    pub materials: BTreeMap<String, u32>,
+    /// Repeating jobs wait until this before being handed out again.
+    #[serde(skip)]
+    pub not_before: Option<Instant>,
+    /// Why the job failed, if it did. Also set while a failed job waits to
+    /// be tried again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Runs that failed so far.
+    pub attempts: u32,
+    /// What `check_progress` has counted so far, built on first use.
+    #[serde(skip)]
+    tally: Option<Tally>,
 }
 
+/// Cells of a job already done: known cells of an `Explore` box, or targets
+/// holding the right block. Kept up to date from each batch of changed
+/// blocks, so reports don't have to go over the whole cache.
 #[derive(Debug, Clone)]
+struct Tally {
+    min: Point3D,
+    max: Point3D,
+    /// Index into `targets` of the target at each position. Empty when
+    /// every cell in the box counts.
+    wanted: HashMap<Point3D, usize>,
+    done: HashSet<Point3D>,
+}
+
+impl Tally {
+    fn contains(&self, p: Point3D) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x)
+            && (self.min.y..=self.max.y).contains(&p.y)
+            && (self.min.z..=self.max.z).contains(&p.z)
+    }
+
+    fn intersects(&self, [lo, hi]: [Point3D; 2]) -> bool {
+        lo.x <= self.max.x
+            && hi.x >= self.min.x
+            && lo.y <= self.max.y
+            && hi.y >= self.min.y
+            && lo.z <= self.max.z
+            && hi.z >= self.min.z
+    }
+
+    fn apply(&mut self, targets: &[Block], block: &Block) {
+        let p = block.position();
+        if !self.contains(p) {
+            return;
+        }
+        let done = match self.wanted.get(&p) {
+            Some(&i) => block.block_type() == targets[i].block_type(),
+            None => self.wanted.is_empty(),
+        };
+        if done {
+            self.done.insert(p);
+        } else {
+            self.done.remove(&p);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum JobKind {
     /// Move to a specific point.
     Goto { target: Point3D, tolerance: f32 },
@@ -43,9 +131,92 @@ pub enum JobKind {
         spacing: u32,
         lanes: u32,
     },
+
+    /// Dig a straight tunnel with a `width` x `height` cross-section, the
+    /// bottom row level with `start`.
+    Tunnel {
+        start: Point3D,
+        direction: Direction3,
+        length: u32,
+        width: u32,
+        height: u32,
+        /// Place a torch every this many blocks.
+        torch_spacing: Option<u32>,
+        /// Block to fill holes in the floor with.
+        floor: Option<String>,
+    },
+
+    /// Dig a staircase along a horizontal `direction` until reaching `target_y`.
+    Staircase {
+        start: Point3D,
+        direction: Direction3,
+        target_y: i32,
+    },
+
+    /// Place a structure layer by layer, restocking from `storage` when the
+    /// turtle runs out of blocks.
+    Build {
+        origin: Point3D,
+        structure: Structure,
+        storage: Option<Point3D>,
+    },
+
+    /// Fill every empty cell of a box with `block_type`.
+    Fill {
+        top_corner: Point3D,
+        bottom_corner: Point3D,
+        block_type: String,
+        storage: Option<Point3D>,
+    },
+
+    /// Level the box at `target_y`: dig out everything above it and fill
+    /// known gaps at or below it with `fill`.
+    Flatten {
+        top_corner: Point3D,
+        bottom_corner: Point3D,
+        target_y: i32,
+        fill: String,
+        storage: Option<Point3D>,
+    },
+
+    /// Lay a one-wide deck of `block_type` from `start` to `end`.
+    Bridge {
+        start: Point3D,
+        end: Point3D,
+        block_type: String,
+        storage: Option<Point3D>,
+    },
+
+    /// Fell and replant trees planted every `spacing + 1` blocks on the
+    /// bottom layer of the plot, then empty the logs into `chest`.
+    TreeFarm {
+        top_corner: Point3D,
+        bottom_corner: Point3D,
+        spacing: u32,
+        sapling: String,
+        chest: Point3D,
+        interval_secs: u64,
+    },
+
+    /// Harvest mature crops on the bottom layer of the plot, replant them
+    /// from `seed`, then empty the harvest into `chest`.
+    CropFarm {
+        top_corner: Point3D,
+        bottom_corner: Point3D,
+        seed: String,
+        chest: Point3D,
+        interval_secs: u64,
+    },
+
+    /// Map the box until `coverage` (0.0 to 1.0) of its cells are cached.
+    Explore {
+        top_corner: Point3D,
+        bottom_corner: Point3D,
+        coverage: f32,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Direction3 {
     PosX,
     NegX,
@@ -55,14 +226,69 @@ pub enum Direction3 {
     NegZ,
 }
 
-#[derive(Default, Debug)]
+impl Direction3 {
+    pub fn offset(&self) -> Point3D {
+        match self {
+            Direction3::PosX => Point3D::new(1, 0, 0),
+            Direction3::NegX => Point3D::new(-1, 0, 0),
+            Direction3::PosY => Point3D::new(0, 1, 0),
+            Direction3::NegY => Point3D::new(0, -1, 0),
+            Direction3::PosZ => Point3D::new(0, 0, 1),
+            Direction3::NegZ => Point3D::new(0, 0, -1),
+        }
+    }
+
+    pub fn is_horizontal(&self) -> bool {
+        self.offset().y == 0
+    }
+
+    /// A horizontal direction at right angles to this one.
+    pub fn perpendicular(&self) -> Direction3 {
+        match self {
+            Direction3::PosX | Direction3::NegX => Direction3::PosZ,
+            _ => Direction3::PosX,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Jobs {
     jobs: Vec<Job>,
+    next_id: u64,
+    events: Events,
+    // Status and turtle of each job as last sent in `job_changed`
+    published: HashMap<JobId, (JobStatus, Option<u32>)>,
 }
 
 impl Jobs {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(events: Events) -> Self {
+        Jobs {
+            jobs: Vec::new(),
+            next_id: 0,
+            events,
+            published: HashMap::new(),
+        }
+    }
+
+    /// Send `job_changed` for every job whose status or turtle changed since
+    /// the last call. Call it after changing jobs.
+    pub fn publish_changes(&mut self) {
+        for job in &self.jobs {
+            let state = (job.status, job.assigned_to);
+            if self.published.get(&job.id) != Some(&state) {
+                self.published.insert(job.id, state);
+                self.events.publish(Event::JobChanged {
+                    job: job.id,
+                    status: job.status,
+                    assigned_to: job.assigned_to,
+                });
+            }
+        }
+    }
+
+    pub fn next_id(&mut self) -> JobId {
+        self.next_id += 1;
+        JobId(self.next_id)
     }
 
     pub fn add(&mut self, job: Job) -> JobId {
@@ -70,19 +296,449 @@ impl Jobs {
         job.id
     }
 
+    pub fn get(&self, id: JobId) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Job> {
         self.jobs.iter_mut()
     }
+
+    /// Update the progress of jobs in `world_id` from blocks that just
+    /// changed there. Paused jobs and ones waiting to be retried keep
+    /// counting, so their tally is still right when they're picked up again.
+    pub fn blocks_changed(&mut self, world_id: &WorldId, world: &World, changed: &[Block]) {
+        let Some(region) = audit::region_of(changed.iter().map(Block::position)) else {
+            return;
+        };
+        for job in &mut self.jobs {
+            if job.world != *world_id {
+                continue;
+            }
+            match job.status {
+                JobStatus::InProgress => job.check_progress(world, changed, region),
+                JobStatus::Pending | JobStatus::Paused if job.tally.is_some() => {
+                    job.check_progress(world, changed, region)
+                }
+                JobStatus::Pending | JobStatus::Paused => {}
+                JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled => job.tally = None,
+            }
+        }
+    }
+
+    /// Count the progress of every job in progress over again, after the
+    /// cache was replaced.
+    pub fn recount(&mut self, worlds: &Worlds) {
+        for job in &mut self.jobs {
+            job.tally = None;
+            if job.status == JobStatus::InProgress {
+                job.recount(worlds.get(&job.world));
+            }
+        }
+    }
+
+    /// The unfinished job currently assigned to a turtle, if any.
+    pub fn assigned_to_mut(&mut self, turtle_id: u32) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| {
+            j.assigned_to == Some(turtle_id)
+                && matches!(j.status, JobStatus::Pending | JobStatus::InProgress)
+        })
+    }
+
+    pub fn get_mut(&mut self, id: JobId) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    /// Pause whatever job the turtle is on, keeping it assigned.
+    pub fn pause(&mut self, turtle_id: u32) {
+        if let Some(job) = self.assigned_to_mut(turtle_id) {
+            info!("Paused job {:?}", job.id);
+            job.status = JobStatus::Paused;
+        }
+    }
+
+    /// Put the turtle's paused jobs back in the queue. They start over, since
+    /// a turtle can't pick up halfway through a batch it was pulled off.
+    pub fn unpause(&mut self, turtle_id: u32) {
+        for job in self.jobs.iter_mut() {
+            if job.assigned_to == Some(turtle_id) && job.status == JobStatus::Paused {
+                job.status = JobStatus::Pending;
+            }
+        }
+    }
+
+    pub fn is_busy(&self, turtle_id: u32) -> bool {
+        self.jobs.iter().any(|j| {
+            j.assigned_to == Some(turtle_id)
+                && matches!(j.status, JobStatus::Pending | JobStatus::InProgress)
+        })
+    }
+}
+
+impl JobKind {
+    /// Read any files the job needs from under `schematics`. This blocks.
+    pub fn load_files(&mut self, schematics: &str) -> Result<(), String> {
+        match self {
+            JobKind::Build { structure, .. } => structure.load(schematics),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject jobs that can't be planned, or that cover more than
+    /// `max_cells` cells. Schematics must be loaded first.
+    pub fn validate(&self, max_cells: u64) -> Result<(), String> {
+        if self.cells().is_none_or(|cells| cells > max_cells) {
+            return Err(format!(
+                "Job covers more than the {} cells allowed",
+                max_cells
+            ));
+        }
+        match self {
+            JobKind::Tunnel {
+                width,
+                height,
+                torch_spacing,
+                ..
+            } => {
+                if *width == 0 || *height == 0 {
+                    return Err("Tunnel width and height must be at least 1".to_string());
+                }
+                if *torch_spacing == Some(0) {
+                    return Err("Torch spacing must be at least 1".to_string());
+                }
+                Ok(())
+            }
+            JobKind::Staircase { direction, .. } if !direction.is_horizontal() => {
+                Err("Staircase direction must be horizontal".to_string())
+            }
+            JobKind::Explore { coverage, .. } if !(0.0..=1.0).contains(coverage) => {
+                Err("Explore coverage must be between 0 and 1".to_string())
+            }
+            JobKind::TreeFarm { interval_secs, .. } | JobKind::CropFarm { interval_secs, .. }
+                if *interval_secs == 0 =>
+            {
+                Err("Farm interval must be at least 1 second".to_string())
+            }
+            JobKind::TreeFarm { spacing: 0, .. } => {
+                Err("Tree spacing must be at least 1".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Roughly how many cells the job digs, fills or looks at, or None if
+    /// there are too many to count.
+    pub fn cells(&self) -> Option<u64> {
+        let box_cells = |a: &Point3D, b: &Point3D| {
+            let (min, max) = plan::bounds(*a, *b);
+            let side = |lo: i32, hi: i32| (hi as i64 - lo as i64 + 1) as u64;
+            side(min.x, max.x)
+                .checked_mul(side(min.y, max.y))?
+                .checked_mul(side(min.z, max.z))
+        };
+        match self {
+            JobKind::Goto { .. } => Some(0),
+            JobKind::Quarry {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Fill {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Flatten {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Explore {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::TreeFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::CropFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            } => box_cells(top_corner, bottom_corner),
+            // Two-high lanes, and the cuts between them
+            JobKind::StripMine {
+                length,
+                spacing,
+                lanes,
+                ..
+            } => {
+                let lanes = *lanes as u64;
+                let along = lanes.checked_mul(*length as u64)?;
+                let across = lanes.saturating_sub(1).checked_mul(*spacing as u64 + 1)?;
+                along.checked_add(across)?.checked_mul(2)
+            }
+            JobKind::Tunnel {
+                length,
+                width,
+                height,
+                ..
+            } => (*length as u64)
+                .checked_mul(*width as u64)?
+                .checked_mul(*height as u64),
+            // Three cells dug per tread
+            JobKind::Staircase {
+                start, target_y, ..
+            } => Some((start.y as i64 - *target_y as i64).unsigned_abs() * 3 + 1),
+            // A cell per step, and one more at each corner
+            JobKind::Bridge { start, end, .. } => {
+                let dx = (end.x as i64 - start.x as i64).unsigned_abs();
+                let dz = (end.z as i64 - start.z as i64).unsigned_abs();
+                Some(plan::bridge_steps(*start, *end) as u64 + 1 + dx.min(dz))
+            }
+            JobKind::Build { structure, .. } => structure.block_count().map(|n| n as u64),
+        }
+    }
 }
 
 impl Job {
-    pub fn new(id: JobId, kind: JobKind) -> Self {
+    pub fn new(id: JobId, world: WorldId, kind: JobKind) -> Self {
         Self {
             id,
             status: JobStatus::Pending,
             progress: 0.0,
             assigned_to: None,
+            world,
             kind,
+            steps: Vec::new(),
+            estimate: Estimate::default(),
+            targets: Vec::new(),
+            materials: BTreeMap::new(),
+            not_before: None,
+            error: None,
+            attempts: 0,
+            tally: None,
+        }
+    }
+
+    /// How long a repeating job waits between runs.
+    pub fn repeat_every(&self) -> Option<Duration> {
+        match &self.kind {
+            JobKind::TreeFarm { interval_secs, .. } | JobKind::CropFarm { interval_secs, .. } => {
+                Some(Duration::from_secs(*interval_secs))
+            }
+            _ => None,
+        }
+    }
+
+    /// Items a turtle must already be carrying to take the job.
+    pub fn required_items(&self) -> Vec<&str> {
+        match &self.kind {
+            JobKind::TreeFarm { sapling, .. } => vec![sapling.as_str()],
+            JobKind::CropFarm { seed, .. } => vec![seed.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.not_before.is_none_or(|t| Instant::now() >= t)
+    }
+
+    /// Mark the job as run. Repeating jobs go back in the queue.
+    pub fn finish(&mut self) {
+        match self.repeat_every() {
+            Some(every) => {
+                info!("Job {:?} finished, running again in {:?}", self.id, every);
+                self.status = JobStatus::Pending;
+                self.assigned_to = None;
+                self.progress = 0.0;
+                self.not_before = Some(Instant::now() + every);
+            }
+            None => {
+                info!("Job {:?} finished", self.id);
+                self.status = JobStatus::Done;
+                self.progress = 1.0;
+            }
+        }
+    }
+
+    /// Mark a run as failed. The job goes back in the queue to start over,
+    /// from wherever the next turtle is, until it has failed `MAX_ATTEMPTS`
+    /// times.
+    pub fn fail(&mut self, error: String) {
+        self.attempts += 1;
+        if self.attempts < MAX_ATTEMPTS {
+            warn!(
+                "Job {:?} failed, trying again ({} of {})",
+                self.id, self.attempts, MAX_ATTEMPTS
+            );
+            self.status = JobStatus::Pending;
+            self.assigned_to = None;
+            self.progress = 0.0;
+            self.not_before = Some(Instant::now() + RETRY_AFTER);
+        } else {
+            warn!(
+                "Job {:?} failed {} times, giving up",
+                self.id, self.attempts
+            );
+            self.status = JobStatus::Failed;
+        }
+        self.error = Some(error);
+    }
+
+    /// Expand the job into instructions and estimate what running them costs,
+    /// given the world's `solid_map`. This can take a while for big jobs.
+    pub fn plan(&mut self, solid: &HashMap<Point3D, bool>) -> Result<(), String> {
+        self.steps = match &self.kind {
+            JobKind::Build {
+                origin,
+                structure,
+                storage,
+            } => {
+                self.targets = structure.resolve(*origin)?;
+                self.materials = schematic::materials(&self.targets);
+                let above = origin.offset(Point3D::new(0, 1, 0), 1);
+                plan::build(above, &self.targets, *storage)
+            }
+            JobKind::Fill {
+                top_corner,
+                bottom_corner,
+                block_type,
+                storage,
+            } => {
+                self.targets = plan::fill_targets(*top_corner, *bottom_corner, block_type, solid);
+                self.materials = schematic::materials(&self.targets);
+                plan::fill(self.path_goal().unwrap(), &self.targets, *storage, solid)
+            }
+            JobKind::Flatten {
+                top_corner,
+                bottom_corner,
+                target_y,
+                fill,
+                storage,
+            } => {
+                self.targets =
+                    plan::flatten_targets(*top_corner, *bottom_corner, *target_y, fill, solid);
+                self.materials = schematic::materials(&self.targets);
+                plan::flatten(*top_corner, &self.targets, *storage, solid)
+            }
+            JobKind::Bridge {
+                start,
+                end,
+                block_type,
+                storage,
+            } => {
+                self.targets = plan::bridge_targets(*start, *end, block_type, solid);
+                self.materials = schematic::materials(&self.targets);
+                plan::build(self.path_goal().unwrap(), &self.targets, *storage)
+            }
+            JobKind::Explore {
+                top_corner,
+                bottom_corner,
+                coverage,
+            } => {
+                let known = solid.keys().copied().collect();
+                plan::explore(*top_corner, *bottom_corner, *coverage, &known)
+            }
+            kind => plan::expand(kind),
+        };
+        if let Some(start) = self.path_goal() {
+            self.estimate = Estimate::from_steps(&self.steps, start, 0, solid);
+        }
+        Ok(())
+    }
+
+    /// Check the job's target blocks against the cache. Leaves the job alone;
+    /// `check_progress` is what moves it along.
+    pub fn verify(&self, world: &World) -> Option<schematic::BuildCheck> {
+        if self.targets.is_empty() {
+            return None;
+        }
+        Some(schematic::verify(&self.targets, world))
+    }
+
+    /// Update progress from blocks that just changed in the job's world,
+    /// whose bounds are `region`, finishing the job early once the cache
+    /// shows its work is done. The first call counts the whole cache.
+    pub fn check_progress(&mut self, world: &World, changed: &[Block], region: [Point3D; 2]) {
+        match &mut self.tally {
+            Some(tally) if !tally.intersects(region) => return,
+            Some(tally) => {
+                for block in changed {
+                    tally.apply(&self.targets, block);
+                }
+            }
+            None => return self.recount(world),
+        }
+        self.update_progress();
+    }
+
+    /// Count the job's done cells over again from the whole cache.
+    pub fn recount(&mut self, world: &World) {
+        let (min, max, wanted) = match &self.kind {
+            JobKind::Explore {
+                top_corner,
+                bottom_corner,
+                ..
+            } => {
+                let (min, max) = plan::bounds(*top_corner, *bottom_corner);
+                (min, max, HashMap::new())
+            }
+            _ => {
+                let Some([min, max]) = audit::region_of(self.targets.iter().map(Block::position))
+                else {
+                    return;
+                };
+                let wanted = self
+                    .targets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| (b.position(), i))
+                    .collect();
+                (min, max, wanted)
+            }
+        };
+        let mut tally = Tally {
+            min,
+            max,
+            wanted,
+            done: HashSet::new(),
+        };
+        for block in world.blocks_in(min, max) {
+            tally.apply(&self.targets, block);
+        }
+        self.tally = Some(tally);
+        self.update_progress();
+    }
+
+    fn update_progress(&mut self) {
+        let Some(tally) = &self.tally else {
+            return;
+        };
+        if let JobKind::Explore { coverage, .. } = &self.kind {
+            let (min, max) = (tally.min, tally.max);
+            let total = (max.x.abs_diff(min.x) as u64 + 1)
+                * (max.y.abs_diff(min.y) as u64 + 1)
+                * (max.z.abs_diff(min.z) as u64 + 1);
+            let reached = tally.done.len() as f32 / total as f32;
+            self.progress = (reached / coverage.max(f32::EPSILON)).min(1.0);
+            if reached >= *coverage && self.status == JobStatus::InProgress {
+                info!("Job {:?} reached {:.0}% coverage", self.id, reached * 100.0);
+                self.status = JobStatus::Done;
+            }
+            return;
+        }
+        let placed = tally.done.len();
+        self.progress = placed as f32 / self.targets.len() as f32;
+        if placed == self.targets.len() && self.status == JobStatus::InProgress {
+            info!("Job {:?} verified complete", self.id);
+            self.status = JobStatus::Done;
         }
     }
 
@@ -91,6 +747,166 @@ impl Job {
             JobKind::Goto { target, .. } => Some(*target),
             JobKind::Quarry { top_corner, .. } => Some(*top_corner),
             JobKind::StripMine { start, .. } => Some(*start),
+            JobKind::Tunnel { start, .. } => Some(*start),
+            JobKind::Staircase { start, .. } => Some(*start),
+            // Blocks are placed from above, so start one up
+            JobKind::Build { origin, .. } => Some(origin.offset(Point3D::new(0, 1, 0), 1)),
+            JobKind::Fill {
+                top_corner,
+                bottom_corner,
+                ..
+            } => {
+                let (_, max) = plan::bounds(*top_corner, *bottom_corner);
+                Some(Point3D::new(top_corner.x, max.y + 1, top_corner.z))
+            }
+            JobKind::Flatten { top_corner, .. } => Some(*top_corner),
+            JobKind::Bridge { start, .. } => Some(start.offset(Point3D::new(0, 1, 0), 1)),
+            JobKind::TreeFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            } => {
+                let (min, _) = plan::bounds(*top_corner, *bottom_corner);
+                Some(Point3D::new(min.x, min.y, min.z - 1))
+            }
+            JobKind::CropFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            } => {
+                let (min, _) = plan::bounds(*top_corner, *bottom_corner);
+                Some(Point3D::new(min.x, min.y + 1, min.z))
+            }
+            JobKind::Explore { top_corner, .. } => Some(*top_corner),
         }
     }
+
+    /// The box the job works in, for the audit log and maps. Jobs without corners
+    /// use the blocks they planned to place, or just where they start.
+    pub fn region(&self) -> Option<[Point3D; 2]> {
+        let corners = match &self.kind {
+            JobKind::Quarry {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Fill {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Flatten {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::TreeFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::CropFarm {
+                top_corner,
+                bottom_corner,
+                ..
+            }
+            | JobKind::Explore {
+                top_corner,
+                bottom_corner,
+                ..
+            } => vec![*top_corner, *bottom_corner],
+            JobKind::Bridge { start, end, .. } => vec![*start, *end],
+            _ if !self.targets.is_empty() => self.targets.iter().map(|b| b.position()).collect(),
+            _ => self.path_goal().into_iter().collect(),
+        };
+        audit::region_of(corners)
+    }
+
+    /// Where the turtle unloads when its inventory fills up, if anywhere.
+    pub fn unload_point(&self) -> Option<Point3D> {
+        match &self.kind {
+            JobKind::Quarry {
+                storage, dump_site, ..
+            } => storage.or(*dump_site),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(world: &mut World, jobs: &mut Jobs, blocks: &[(i32, i32, i32, &str)]) {
+        let blocks = blocks
+            .iter()
+            .map(|&(x, y, z, name)| Block::new(Point3D::new(x, y, z), name.to_string()))
+            .collect();
+        let changed = world.set_blocks(blocks);
+        jobs.blocks_changed(&WorldId::default(), world, &changed);
+    }
+
+    fn started(jobs: &mut Jobs, kind: JobKind, targets: Vec<Block>) -> JobId {
+        let id = jobs.next_id();
+        let mut job = Job::new(id, WorldId::default(), kind);
+        job.status = JobStatus::InProgress;
+        job.targets = targets;
+        jobs.add(job)
+    }
+
+    #[test]
+    fn explore_counts_cells_as_they_become_known() {
+        let mut world = World::new();
+        // Known before the job starts, and outside its box
+        report(
+            &mut world,
+            &mut Jobs::new(Events::new()),
+            &[(0, 0, 0, "stone")],
+        );
+        let mut jobs = Jobs::new(Events::new());
+        let kind = JobKind::Explore {
+            top_corner: Point3D::new(0, 0, 0),
+            bottom_corner: Point3D::new(1, 0, 1),
+            coverage: 1.0,
+        };
+        let id = started(&mut jobs, kind, Vec::new());
+
+        report(&mut world, &mut jobs, &[(5, 5, 5, "stone")]);
+        assert_eq!(jobs.get(id).unwrap().progress, 0.25);
+        // Seeing a known cell again doesn't count twice
+        report(&mut world, &mut jobs, &[(0, 0, 0, "dirt"), (1, 0, 0, "a")]);
+        assert_eq!(jobs.get(id).unwrap().progress, 0.5);
+        report(&mut world, &mut jobs, &[(0, 0, 1, "a"), (1, 0, 1, "a")]);
+        let job = jobs.get(id).unwrap();
+        assert_eq!(job.progress, 1.0);
+        assert_eq!(job.status, JobStatus::Done);
+    }
+
+    #[test]
+    fn targets_count_only_the_right_block() {
+        let mut world = World::new();
+        let mut jobs = Jobs::new(Events::new());
+        let targets = vec![
+            Block::new(Point3D::new(0, 0, 0), "minecraft:stone".to_string()),
+            Block::new(Point3D::new(1, 0, 0), "minecraft:dirt".to_string()),
+        ];
+        let kind = JobKind::Goto {
+            target: Point3D::new(0, 1, 0),
+            tolerance: 0.0,
+        };
+        let id = started(&mut jobs, kind, targets);
+
+        report(&mut world, &mut jobs, &[(0, 0, 0, "minecraft:stone")]);
+        assert_eq!(jobs.get(id).unwrap().progress, 0.5);
+        // Broken again, then the other one placed wrong
+        report(&mut world, &mut jobs, &[(0, 0, 0, "minecraft:air")]);
+        report(&mut world, &mut jobs, &[(1, 0, 0, "minecraft:stone")]);
+        assert_eq!(jobs.get(id).unwrap().progress, 0.0);
+        report(
+            &mut world,
+            &mut jobs,
+            &[(0, 0, 0, "minecraft:stone"), (1, 0, 0, "minecraft:dirt")],
+        );
+        assert_eq!(jobs.get(id).unwrap().status, JobStatus::Done);
+    }
 }
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::Message;
+use crate::auth::Identity;
+use crate::state::AppState;
+
+/// The `[limits]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Steady request rate allowed per turtle or user.
+    pub requests_per_second: f64,
+    /// How many requests can come at once before the rate kicks in.
+    pub burst: u32,
+    /// Every request from an address counts against this before it's
+    /// authenticated, so it must cover every turtle on one Minecraft server.
+    pub address_requests_per_second: f64,
+    pub address_burst: u32,
+    /// `/request-path` gets a tighter limit of its own, since every call
+    /// builds a grid and runs A*.
+    pub path_searches_per_minute: f64,
+    pub path_search_burst: u32,
+    /// Path searches running at once, across everyone.
+    pub max_path_searches: usize,
+    /// Largest `radius` a `/scan` upload may fill with air.
+    pub max_scan_radius: i32,
+    /// Most cells a job may cover, since big ones take a while to plan.
+    pub max_job_cells: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            requests_per_second: 20.0,
+            burst: 40,
+            address_requests_per_second: 200.0,
+            address_burst: 400,
+            path_searches_per_minute: 30.0,
+            path_search_burst: 5,
+            max_path_searches: 4,
+            max_scan_radius: 32,
+            max_job_cells: 250_000,
+        }
+    }
+}
+
+// Buckets kept before full ones are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// A token bucket per caller.
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        RateLimiter {
+            per_second,
+            burst: burst.max(1) as f64,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Change the rate and burst, keeping what callers have already used.
+    pub fn set_rate(&mut self, per_second: f64, burst: u32) {
+        self.per_second = per_second;
+        self.burst = burst.max(1) as f64;
+    }
+
+    /// Take a token for `key`. If there isn't one, says how long until there
+    /// will be.
+    pub fn check(&mut self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        // Anyone can get a bucket of their own by address, so once there are
+        // too many, forget the tenth that were used longest ago
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(key) {
+            let mut used: Vec<Instant> = self.buckets.values().map(|b| b.last).collect();
+            let (_, &mut cutoff, _) = used.select_nth_unstable(MAX_BUCKETS / 10);
+            self.buckets.retain(|_, b| b.last > cutoff);
+        }
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+        });
+        let refill = now.duration_since(bucket.last).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.per_second <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.per_second,
+        ))
+    }
+}
+
+/// 429 with a `Retry-After` in whole seconds.
+pub fn too_many_requests(wait: Duration) -> Response {
+    let secs = wait.as_secs_f64().ceil().min(u32::MAX as f64) as u32;
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(Message {
+            text: format!("Rate limited, try again in {}s", secs),
+        }),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
+
+/// Layer applying `Limits::address_requests_per_second` to every request by
+/// the address it comes from. Runs before `auth::authenticate`, so requests
+/// with bad credentials or signatures are limited too.
+pub async fn limit_address(State(st): State<AppState>, req: Request, next: Next) -> Response {
+    let key = match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => addr.ip().to_string(),
+        None => "unknown".to_string(),
+    };
+    if let Err(wait) = st.address_limiter.write().await.check(&key) {
+        return too_many_requests(wait);
+    }
+    next.run(req).await
+}
+
+/// Layer applying `Limits::requests_per_second` to each turtle and user. Runs
+/// after `auth::authenticate`; requests without an identity are only limited
+/// by address.
+pub async fn rate_limit(State(st): State<AppState>, req: Request, next: Next) -> Response {
+    if let Some(identity) = req.extensions().get::<Identity>()
+        && let Err(wait) = st.rate_limiter.write().await.check(&identity.to_string())
+    {
+        return too_many_requests(wait);
+    }
+    next.run(req).await
+}
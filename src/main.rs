@@ -1,106 +1,630 @@
 #![allow(dead_code)]
+mod audit;
+mod auth;
+mod config;
+mod estimate;
+mod events;
 mod job;
+mod limit;
+mod metrics;
 mod pathfinder;
+mod plan;
+mod region;
+mod render;
+mod scheduler;
+mod schematic;
+mod socket;
 mod state;
 mod turtle;
 use axum::http::HeaderMap;
 use pathfinder::Point3D;
 
-use crate::job::{Job, Jobs};
-use crate::turtle::{Block, Turtles, World};
+use crate::audit::{Action, AuditQuery};
+use crate::auth::{Access, Credentials, Identity, Role, TurtleId};
+use crate::config::Config;
+use crate::estimate::Estimate;
+use crate::events::Event;
+use crate::job::{Job, JobId, JobKind, JobStatus};
+use crate::metrics::METRICS;
+use crate::turtle::{Batch, Block, Fuel, Hold, Item, Turtles, WorldId, Worlds};
 use serde::{Deserialize, Serialize};
 use state::AppState;
+use std::collections::BTreeMap;
 use std::time::Duration;
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{NotForContentType, Predicate, SizeAbove},
+};
+use tracing::{debug, info, warn};
+
+use clap::Parser;
 
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
     http::StatusCode,
-    response::IntoResponse,
+    middleware::{self, Next},
+    response::{Html, IntoResponse},
     routing::{get, post},
 };
 
-const SAVE_PATH: &str = "data/world.bin";
-const SAVE_EVERY: Duration = Duration::from_secs(120);
-
-#[derive(Serialize, Deserialize)]
+// `steps[i]` has sequence number `start + i` within batch `id`. Batch 0 is
+// never tracked, e.g. a manual `/request-path`. When resuming partway through
+// a counted move like `forward 37`, `moved` of its moves were already made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Instructions {
+    id: u64,
+    start: u32,
+    #[serde(default)]
+    moved: u32,
     steps: Vec<String>,
 }
 impl Instructions {
     fn new() -> Self {
-        Instructions { steps: Vec::new() }
+        Instructions {
+            id: 0,
+            start: 0,
+            moved: 0,
+            steps: Vec::new(),
+        }
     }
 }
 
+// Where the block cache was saved before it was split up by world. It's
+// loaded into the default world if nothing newer is saved.
+const LEGACY_WORLD_PATH: &str = "data/world.bin";
+
 #[derive(Serialize, Deserialize)]
 struct Message {
     text: String,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    secret_key: String,
-    port: String,
-}
-
-fn key_is_valid(key: &str) -> bool {
-    let config: Config = toml::from_str(
-        &std::fs::read_to_string("config.toml").expect("Failed to read config.toml"),
-    )
-    .expect("Failed to parse config.toml");
-    config.secret_key == key
-}
-
 #[tokio::main]
 async fn main() {
-    let mut main_world = World::new();
-    let _ = main_world.load_world(SAVE_PATH);
-    let turtles = Turtles::new();
-    let jobs = Jobs::new();
-    let app_state = AppState::new(main_world, turtles, jobs);
+    let args = config::Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(config.logging.level())
+        .init();
+
+    let mut worlds = Worlds::load(&config.storage.worlds).expect("Failed to load saved worlds");
+    if worlds.block_count() == 0 && std::path::Path::new(LEGACY_WORLD_PATH).exists() {
+        let default = WorldId::default();
+        worlds
+            .get_mut(&default)
+            .load_world(LEGACY_WORLD_PATH)
+            .expect("Failed to load the old world save");
+        info!("Moved {} into {}", LEGACY_WORLD_PATH, default);
+    }
+    let credentials =
+        Credentials::load(&config.storage.credentials).expect("Failed to load turtle credentials");
+    let audit = audit::AuditLog::open(&config.storage.audit).expect("Failed to open the audit log");
+    let bind = config.bind_address();
+    let save_path = config.storage.worlds.clone();
+    let save_every = config.save_every();
+    let app_state = AppState::new(worlds, credentials, audit, config, args);
 
     tokio::spawn(start_periodic_saves(
         app_state.clone(),
-        SAVE_PATH.into(),
-        SAVE_EVERY,
+        save_path,
+        save_every,
     ));
+    tokio::spawn(config::watch(app_state.clone()));
+    tokio::spawn(events::watch_turtles(app_state.clone()));
+    #[cfg(unix)]
+    tokio::spawn(config::reload_on_hangup(app_state.clone()));
 
-    tracing_subscriber::fmt::init();
-    let config: Config = toml::from_str(
-        &std::fs::read_to_string("config.toml").expect("Failed to read config.toml"),
-    )
-    .expect("Failed to parse config.toml");
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/request-path", post(path_request))
+    let turtle_routes = Router::new()
         .route("/update-block", post(block_update))
+        .route("/inventory", post(inventory_update))
+        .route(
+            "/scan",
+            post(scan_upload).layer(DefaultBodyLimit::max(auth::MAX_SIGNED_BODY)),
+        )
+        .route("/report-failure", post(report_failure))
         .route("/get-instructions", get(get_instructions))
+        .route("/ws", get(socket::turtle_socket))
+        .route_layer(middleware::from_fn(|req: Request, next: Next| {
+            auth::require(Access::Turtles, req, next)
+        }));
+    let path_routes = Router::new()
+        .route("/request-path", post(path_request))
+        .route_layer(middleware::from_fn(|req: Request, next: Next| {
+            auth::require(Access::TurtlesOrUsers(Role::Operator), req, next)
+        }));
+    let viewer_routes = Router::new()
+        .route("/turtles", get(list_turtles))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{id}/verify", get(verify_job))
+        .route("/world/blocks", get(region::get_blocks))
+        .route("/world/block", get(region::get_block))
+        .route("/world/counts", get(region::count_blocks))
+        .route("/world/heightmap", get(region::heightmap))
+        .route("/world/render", get(render::render_map))
+        .route("/events", get(events::stream_events))
+        .route("/metrics", get(metrics::export))
+        .route_layer(middleware::from_fn(|req: Request, next: Next| {
+            auth::require(Access::Users(Role::Viewer), req, next)
+        }));
+    let operator_routes = Router::new()
+        .route("/jobs", post(create_job))
+        .route("/jobs/{id}/cancel", post(cancel_job))
+        .route("/turtles/{id}/stop", post(stop_turtle))
+        .route("/fleet/stop", post(fleet_stop))
+        .route("/fleet/recall", post(fleet_recall))
+        .route("/fleet/release", post(fleet_release))
+        .route_layer(middleware::from_fn(|req: Request, next: Next| {
+            auth::require(Access::Users(Role::Operator), req, next)
+        }));
+    let admin_routes = Router::new()
+        .route("/turtles/{id}/revoke", post(revoke_turtle))
+        .route("/world/restore", post(restore_world))
+        .route("/audit", get(query_audit))
+        .route("/config/reload", post(reload_config))
+        .route_layer(middleware::from_fn(|req: Request, next: Next| {
+            auth::require(Access::Users(Role::Admin), req, next)
+        }));
+
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/register", post(register_turtle))
+        .merge(turtle_routes)
+        .merge(path_routes)
+        .merge(viewer_routes)
+        .merge(operator_routes)
+        .merge(admin_routes)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            limit::rate_limit,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::authenticate,
+        ))
+        // Outermost of the three, so nothing gets verified or buffered for
+        // an address that's over its limit
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            limit::limit_address,
+        ))
+        .layer(middleware::from_fn(metrics::track_requests))
+        // Large batches are gzipped for clients that ask for it. Event
+        // streams aren't, since that would hold events back in the buffer.
+        .layer(
+            CompressionLayer::new().compress_when(SizeAbove::new(1024).and(NotForContentType::SSE)),
+        )
         .with_state(app_state.clone());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:".to_string() + &config.port)
+    let listener = tokio::net::TcpListener::bind(bind)
         .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap();
+        .unwrap_or_else(|e| panic!("Failed to bind {}: {}", bind, e));
+    info!("Listening on {}", bind);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
-// basic handler that responds with a static string
-async fn root() -> &'static str {
-    "Turtle Manager v0.1.0"
+// The dashboard is one self-contained page; it signs in as a user and calls
+// the same API as everything else
+async fn root() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
 }
 
 // main endpoint that is gonna get spammed
-async fn get_instructions(State(st): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
-    let instructions = Instructions::new();
-    let world = st.world.read().await;
-    let jobs = st.jobs.read().await;
+async fn get_instructions(
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+) -> impl IntoResponse {
+    match next_instructions(&st, turtle_id).await {
+        Ok(instructions) => (StatusCode::OK, axum::Json(instructions)).into_response(),
+        Err((code, text)) => (
+            code,
+            Json(Message {
+                text: text.to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+// Work out what a turtle should run next, whether it polled for it or is
+// connected over the socket. The trip to a new job is planned without holding
+// the jobs or turtles locks, then checked again before it's handed out.
+async fn next_instructions(
+    st: &AppState,
+    turtle_id: u32,
+) -> Result<Instructions, (StatusCode, &'static str)> {
+    let config = st.config().await;
+    let (job_id, world, position, goal) = {
+        let mut jobs = st.jobs.write().await;
+        let mut turtles = st.turtles.write().await;
+        // Turtles will be registered in the post info handler
+        turtles.seen(turtle_id);
+        let Some(turtle) = turtles.get_turtle(turtle_id) else {
+            return Err((StatusCode::NOT_FOUND, "Turtle not found"));
+        };
+
+        // Pick up where the turtle left off, e.g. after a reboot
+        if let Some(batch) = turtle.batch()
+            && !batch.is_finished()
+        {
+            info!(
+                "Resuming batch {} for turtle {} at step {} after {} moves",
+                batch.id, turtle_id, batch.done, batch.moved
+            );
+            return Ok(resume(batch));
+        }
+        // Held turtles get nothing new until they're released
+        if turtle.hold().is_some() {
+            return Ok(Instructions::new());
+        }
+        let position = turtle.position();
+
+        if let Some(job) = jobs.assigned_to_mut(turtle_id)
+            && job.status == JobStatus::InProgress
+        {
+            job.finish();
+        }
+        if !jobs.is_busy(turtle_id) {
+            scheduler::assign_jobs(&mut jobs, &turtles, config.heartbeat.offline_after());
+        }
+        let Some(job) = jobs
+            .assigned_to_mut(turtle_id)
+            .filter(|job| job.status == JobStatus::Pending)
+        else {
+            jobs.publish_changes();
+            return Ok(Instructions::new());
+        };
+        match job.path_goal() {
+            Some(goal) if goal != position => (job.id, job.world.clone(), position, goal),
+            _ => {
+                let instructions = start_job(job, &mut turtles, turtle_id, Vec::new());
+                jobs.publish_changes();
+                return Ok(instructions);
+            }
+        }
+    };
+
+    let trip = find_path(st, &world, position, goal).await;
+
+    let mut jobs = st.jobs.write().await;
     let mut turtles = st.turtles.write().await;
-    let auth = headers.get("Authorization").unwrap().to_str().unwrap();
-    if !key_is_valid(auth) {
+    let Some(turtle) = turtles.get_turtle(turtle_id) else {
+        return Err((StatusCode::NOT_FOUND, "Turtle not found"));
+    };
+    // Another poll may have started the job while this one was searching
+    if let Some(batch) = turtle.batch()
+        && !batch.is_finished()
+    {
+        return Ok(resume(batch));
+    }
+    if turtle.hold().is_some() || turtle.position() != position {
+        return Ok(Instructions::new());
+    }
+    let Some(job) = jobs
+        .get_mut(job_id)
+        .filter(|job| job.status == JobStatus::Pending && job.assigned_to == Some(turtle_id))
+    else {
+        return Ok(Instructions::new());
+    };
+    let instructions = match trip {
+        Ok(trip) => start_job(job, &mut turtles, turtle_id, trip),
+        Err(error) => {
+            warn!(
+                "No path to job {:?} for turtle {}: {}",
+                job.id, turtle_id, error
+            );
+            job.status = JobStatus::Failed;
+            job.error = Some("No path to the job".to_string());
+            Instructions::new()
+        }
+    };
+    jobs.publish_changes();
+
+    Ok(instructions)
+}
+
+// Start a pending job's batch: the trip there, then the job itself.
+fn start_job(
+    job: &mut Job,
+    turtles: &mut Turtles,
+    turtle_id: u32,
+    trip: Vec<String>,
+) -> Instructions {
+    job.status = JobStatus::InProgress;
+    let mut steps = trip;
+    steps.extend(job.steps.iter().cloned());
+    let steps = pathfinder::run_length(steps);
+    let id = turtles.start_batch(turtle_id, steps.clone(), Some(job.id));
+    Instructions {
+        id,
+        start: 0,
+        moved: 0,
+        steps,
+    }
+}
+
+fn resume(batch: &Batch) -> Instructions {
+    Instructions {
+        id: batch.id,
+        start: batch.done,
+        moved: batch.moved,
+        steps: batch.steps[batch.done as usize..].to_vec(),
+    }
+}
+
+#[derive(Deserialize)]
+struct FailureReport {
+    batch: u64,
+    step: u32,
+    reason: String,
+}
+
+async fn report_failure(
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+    Json(payload): Json<FailureReport>,
+) -> impl IntoResponse {
+    record_failure(&st, turtle_id, payload).await;
+
+    StatusCode::OK.into_response()
+}
+
+// A failed step ends the batch. The job it was running is tried again, up to
+// a point.
+async fn record_failure(st: &AppState, turtle_id: u32, report: FailureReport) {
+    let mut jobs = st.jobs.write().await;
+    let mut turtles = st.turtles.write().await;
+    let Some(batch) = turtles.fail(turtle_id, report.batch, report.step) else {
+        return;
+    };
+    let step = batch
+        .steps
+        .get(report.step as usize)
+        .map(String::as_str)
+        .unwrap_or("?");
+    let error = format!("Step {} ({}) failed: {}", report.step, step, report.reason);
+    warn!("Turtle {}: {}", turtle_id, error);
+    if let Some(job) = batch.job.and_then(|id| jobs.get_mut(id)) {
+        job.fail(error);
+    }
+    jobs.publish_changes();
+    let mut audit = st.audit.write().await;
+    audit.end_batch(turtle_id);
+    audit.record(
+        &Identity::Turtle(turtle_id),
+        Action::StepFailed {
+            batch: report.batch,
+            step: report.step,
+            reason: report.reason,
+        },
+        None,
+        None,
+    );
+}
+
+#[derive(Serialize)]
+struct JobCreated {
+    id: JobId,
+    estimate: Estimate,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, u32>,
+    assigned_to: Option<u32>,
+}
+
+/// A job to create, in the default world unless it says otherwise.
+#[derive(Deserialize)]
+struct JobRequest {
+    #[serde(default)]
+    world: WorldId,
+    #[serde(flatten)]
+    kind: JobKind,
+}
+
+async fn create_job(
+    State(st): State<AppState>,
+    identity: Identity,
+    Json(JobRequest { world, mut kind }): Json<JobRequest>,
+) -> impl IntoResponse {
+    let config = st.config().await;
+    // Files are read on the blocking pool, before any lock is taken
+    let schematics = config.storage.schematics.clone();
+    let loaded = tokio::task::spawn_blocking(move || kind.load_files(&schematics).map(|()| kind))
+        .await
+        .expect("Loading job files panicked");
+    let kind = match loaded {
+        Ok(kind) => kind,
+        Err(text) => return (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    };
+    if let Err(text) = kind.validate(config.limits.max_job_cells) {
+        return (StatusCode::BAD_REQUEST, Json(Message { text })).into_response();
+    }
+
+    // Planning works from a copy of what's known about the world, on the
+    // blocking pool, so it holds up neither the runtime nor the world
+    let solid = st.worlds.read().await.get(&world).solid_map();
+    let id = st.jobs.write().await.next_id();
+    let mut job = Job::new(id, world.clone(), kind);
+    let planned = tokio::task::spawn_blocking(move || job.plan(&solid).map(|()| job))
+        .await
+        .expect("Planning a job panicked");
+    let job = match planned {
+        Ok(job) => job,
+        Err(text) => return (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    };
+
+    let mut jobs = st.jobs.write().await;
+    let turtles = st.turtles.read().await;
+    let estimate = job.estimate;
+    let materials = job.materials.clone();
+    let region = job.region();
+    jobs.add(job);
+    scheduler::assign_jobs(&mut jobs, &turtles, config.heartbeat.offline_after());
+
+    let assigned_to = jobs.get(id).and_then(|j| j.assigned_to);
+    drop((jobs, turtles));
+    st.audit.write().await.record(
+        &identity,
+        Action::JobCreated { job: id },
+        Some(&world),
+        region,
+    );
+    if let Some(turtle_id) = assigned_to {
+        socket::push_instructions(&st, turtle_id).await;
+    }
+    (
+        StatusCode::CREATED,
+        Json(JobCreated {
+            id,
+            estimate,
+            materials,
+            assigned_to,
+        }),
+    )
+        .into_response()
+}
+
+async fn block_update(
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+    Json(payload): Json<StatusUpdate>,
+) -> impl IntoResponse {
+    if !record_status(&st, Some(turtle_id), payload).await {
+        // The turtle was stopped or its job cancelled since it got the batch
+        return (
+            StatusCode::CONFLICT,
+            Json(Message {
+                text: "Batch was cancelled".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
+
+/// Returns false if the turtle reported progress on a batch it should drop.
+async fn record_status(st: &AppState, turtle_id: Option<u32>, payload: StatusUpdate) -> bool {
+    let mut current = true;
+    let mut batch_over = false;
+    let mut world_id = payload.world.clone().unwrap_or_default();
+    let low_fuel = st.config().await.events.low_fuel;
+    if let Some(turtle_id) = turtle_id {
+        let mut turtles = st.turtles.write().await;
+        turtles.report(
+            turtle_id,
+            payload.world,
+            payload.position,
+            payload.rotation,
+            payload.fuel,
+            low_fuel,
+        );
+        if let Some(turtle) = turtles.get_turtle(turtle_id) {
+            world_id = turtle.world().clone();
+        }
+        // Reports sent after a step double as its acknowledgement, and the
+        // ones sent partway through a counted move record how far it got
+        if let (Some(batch), Some(seq)) = (payload.batch, payload.instruction_index) {
+            current = match payload.moved {
+                Some(moved) => turtles.progress(turtle_id, batch, seq, moved),
+                None => turtles.ack(turtle_id, batch, seq),
+            };
+            batch_over = turtles
+                .get_turtle(turtle_id)
+                .and_then(|t| t.batch())
+                .is_none_or(|b| b.id != batch || b.is_finished());
+        }
+    }
+    // Block reports are audited a batch at a time
+    if let Some(turtle_id) = turtle_id {
+        let mut audit = st.audit.write().await;
+        audit.blocks_reported(turtle_id, payload.batch, &world_id, &payload.blocks);
+        if batch_over {
+            audit.end_batch(turtle_id);
+        }
+    }
+    let mut worlds = st.worlds.write().await; // write lock for concurrent writers
+    let world = worlds.get_mut(&world_id);
+    let mut changed = Vec::new();
+    for block in payload.blocks {
+        if world.set_block(block.clone()) {
+            changed.push(block);
+        }
+    }
+    let mut jobs = st.jobs.write().await;
+    jobs.blocks_changed(&world_id, world, &changed);
+    jobs.publish_changes();
+    if !changed.is_empty() {
+        st.events.publish(Event::BlocksChanged {
+            world: world_id.clone(),
+            blocks: changed,
+        });
+    }
+    current
+}
+
+async fn cancel_job(
+    State(st): State<AppState>,
+    identity: Identity,
+    Path(id): Path<JobId>,
+) -> impl IntoResponse {
+    let mut jobs = st.jobs.write().await;
+    let Some(job) = jobs.get_mut(id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(Message {
+                text: "Job not found".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    job.status = JobStatus::Cancelled;
+    let (world, region, assigned_to) = (job.world.clone(), job.region(), job.assigned_to);
+    jobs.publish_changes();
+    st.audit.write().await.record(
+        &identity,
+        Action::JobCancelled { job: id },
+        Some(&world),
+        region,
+    );
+    if let Some(turtle_id) = assigned_to {
+        st.turtles.write().await.clear_batch(turtle_id);
+        let connections = st.connections.read().await;
+        connections.send(turtle_id, socket::ServerMessage::Cancel { job: id });
+    }
+    info!("Cancelled job {:?}", id);
+
+    StatusCode::OK.into_response()
+}
+
+#[derive(Serialize)]
+struct Registered {
+    token: String,
+}
+
+// A new turtle trades the registration key for a token of its own. Turtles
+// that already have one must be revoked first, so the registration key alone
+// can't be used to take over an existing turtle.
+async fn register_turtle(State(st): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let config = st.config().await;
+    if !auth::registration_is_valid(&config, auth) {
         return (
             StatusCode::UNAUTHORIZED,
             Json(Message {
-                text: "Invalid secret key".to_string(),
+                text: "Invalid registration key".to_string(),
             }),
         )
             .into_response();
@@ -121,91 +645,742 @@ async fn get_instructions(State(st): State<AppState>, headers: HeaderMap) -> imp
                 .into_response();
         }
     };
-    let turtle = match turtles.get_turtle(turtle_id) {
-        Some(t) => Some(t),
-        // Turtles will be registered in the post info handler
-        None => {
+    let mut credentials = st.credentials.write().await;
+    if credentials.contains(turtle_id) {
+        return (
+            StatusCode::CONFLICT,
+            Json(Message {
+                text: "Turtle is already registered".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let token = credentials.issue(turtle_id);
+    if let Err(e) = credentials.save(&config.storage.credentials) {
+        warn!("Failed to save credentials: {}", e);
+    }
+    info!("Issued a token to turtle {}", turtle_id);
+    // Whoever had the registration key now is this turtle
+    st.audit.write().await.record(
+        &Identity::Turtle(turtle_id),
+        Action::TokenIssued { turtle: turtle_id },
+        None,
+        None,
+    );
+
+    Json(Registered { token }).into_response()
+}
+
+async fn revoke_turtle(
+    State(st): State<AppState>,
+    identity: Identity,
+    Path(turtle_id): Path<u32>,
+) -> impl IntoResponse {
+    let mut credentials = st.credentials.write().await;
+    if !credentials.revoke(turtle_id) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(Message {
+                text: "Turtle has no credentials".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if let Err(e) = credentials.save(&st.config().await.storage.credentials) {
+        warn!("Failed to save credentials: {}", e);
+    }
+    info!("Revoked turtle {}'s token", turtle_id);
+    st.audit.write().await.record(
+        &identity,
+        Action::TokenRevoked { turtle: turtle_id },
+        None,
+        None,
+    );
+    // Its socket closes the next time it sends anything
+    let connections = st.connections.read().await;
+    connections.send(
+        turtle_id,
+        socket::ServerMessage::Error {
+            text: "Token revoked".to_string(),
+        },
+    );
+
+    StatusCode::OK.into_response()
+}
+
+async fn stop_turtle(
+    State(st): State<AppState>,
+    identity: Identity,
+    Path(turtle_id): Path<u32>,
+) -> impl IntoResponse {
+    let ids = st.turtles.read().await.select(Some(&[turtle_id]));
+    if ids.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(Message {
+                text: "Turtle not found".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    halt_turtles(&st, &ids).await;
+    st.audit.write().await.record(
+        &identity,
+        Action::TurtlesStopped { turtles: ids },
+        None,
+        None,
+    );
+
+    StatusCode::OK.into_response()
+}
+
+/// Which turtles a fleet command applies to. Leaving `turtles` out means
+/// every registered turtle.
+#[derive(Deserialize, Default)]
+struct FleetCommand {
+    turtles: Option<Vec<u32>>,
+}
+
+#[derive(Serialize)]
+struct FleetResult {
+    turtles: Vec<u32>,
+    /// Recalled turtles that couldn't find a way home and were halted instead.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    no_path_home: Vec<u32>,
+}
+
+async fn fleet_stop(
+    State(st): State<AppState>,
+    identity: Identity,
+    payload: Option<Json<FleetCommand>>,
+) -> impl IntoResponse {
+    let Json(command) = payload.unwrap_or_default();
+    let ids = st.turtles.read().await.select(command.turtles.as_deref());
+    halt_turtles(&st, &ids).await;
+    st.audit.write().await.record(
+        &identity,
+        Action::TurtlesStopped {
+            turtles: ids.clone(),
+        },
+        None,
+        None,
+    );
+
+    Json(FleetResult {
+        turtles: ids,
+        no_path_home: Vec::new(),
+    })
+    .into_response()
+}
+
+async fn fleet_recall(
+    State(st): State<AppState>,
+    identity: Identity,
+    payload: Option<Json<FleetCommand>>,
+) -> impl IntoResponse {
+    let Json(command) = payload.unwrap_or_default();
+    let ids = st.turtles.read().await.select(command.turtles.as_deref());
+    let no_path_home = recall_turtles(&st, &ids).await;
+    st.audit.write().await.record(
+        &identity,
+        Action::TurtlesRecalled {
+            turtles: ids.clone(),
+        },
+        None,
+        None,
+    );
+
+    Json(FleetResult {
+        turtles: ids,
+        no_path_home,
+    })
+    .into_response()
+}
+
+async fn fleet_release(
+    State(st): State<AppState>,
+    identity: Identity,
+    payload: Option<Json<FleetCommand>>,
+) -> impl IntoResponse {
+    let Json(command) = payload.unwrap_or_default();
+    let ids = st.turtles.read().await.select(command.turtles.as_deref());
+    {
+        let mut jobs = st.jobs.write().await;
+        let mut turtles = st.turtles.write().await;
+        for &id in &ids {
+            turtles.release(id);
+            jobs.unpause(id);
+        }
+        jobs.publish_changes();
+    }
+    info!("Released turtles {:?}", ids);
+    st.audit.write().await.record(
+        &identity,
+        Action::TurtlesReleased {
+            turtles: ids.clone(),
+        },
+        None,
+        None,
+    );
+    for &id in &ids {
+        socket::push_instructions(&st, id).await;
+    }
+
+    Json(FleetResult {
+        turtles: ids,
+        no_path_home: Vec::new(),
+    })
+    .into_response()
+}
+
+// Stop turtles where they are and pause their jobs. Connected turtles are told
+// right away, polling ones find out from their next status report.
+async fn halt_turtles(st: &AppState, ids: &[u32]) {
+    {
+        let mut jobs = st.jobs.write().await;
+        let mut turtles = st.turtles.write().await;
+        for &id in ids {
+            turtles.set_hold(id, Hold::Halted);
+            jobs.pause(id);
+        }
+        jobs.publish_changes();
+    }
+    info!("Halted turtles {:?}", ids);
+    let connections = st.connections.read().await;
+    for &id in ids {
+        connections.send(id, socket::ServerMessage::Stop);
+    }
+}
+
+// Pause the turtles' jobs and send them home. Returns the ones with no path
+// home, which are halted where they are. The turtles are halted first, so
+// they stop right away while their way home is planned.
+async fn recall_turtles(st: &AppState, ids: &[u32]) -> Vec<u32> {
+    let mut trips = Vec::new();
+    let mut no_path = Vec::new();
+    {
+        let mut jobs = st.jobs.write().await;
+        let mut turtles = st.turtles.write().await;
+        for &id in ids {
+            jobs.pause(id);
+            let Some(turtle) = turtles.get_turtle(id) else {
+                continue;
+            };
+            let (home_world, home) = turtle.home();
+            // There's no pathing through portals, so turtles in another
+            // dimension than home can't make it back
+            if turtle.world() == home_world {
+                trips.push((id, home_world.clone(), turtle.position(), home));
+            } else {
+                no_path.push(id);
+            }
+            turtles.set_hold(id, Hold::Halted);
+        }
+        jobs.publish_changes();
+    }
+    {
+        let connections = st.connections.read().await;
+        for &id in ids {
+            connections.send(id, socket::ServerMessage::Stop);
+        }
+    }
+
+    let paths =
+        futures_util::future::join_all(trips.iter().map(|(_, world, position, home)| async move {
+            if position == home {
+                Ok(Vec::new())
+            } else {
+                find_path(st, world, *position, *home).await
+            }
+        }))
+        .await;
+
+    let mut batches = Vec::new();
+    {
+        let mut turtles = st.turtles.write().await;
+        for ((id, _, position, _), path) in trips.into_iter().zip(paths) {
+            // Skip turtles released, or moved, while their path was planned
+            let Some(turtle) = turtles.get_turtle(id) else {
+                continue;
+            };
+            if turtle.hold() != Some(Hold::Halted) || turtle.position() != position {
+                continue;
+            }
+            let Ok(steps) = path else {
+                no_path.push(id);
+                continue;
+            };
+            turtles.set_hold(id, Hold::Recalled);
+            if !steps.is_empty() {
+                let batch = turtles.start_batch(id, steps.clone(), None);
+                batches.push((
+                    id,
+                    Instructions {
+                        id: batch,
+                        start: 0,
+                        moved: 0,
+                        steps,
+                    },
+                ));
+            }
+        }
+    }
+    for id in &no_path {
+        warn!("No path home for turtle {}, halting it", id);
+    }
+    info!("Recalled turtles {:?}", ids);
+    let connections = st.connections.read().await;
+    for (id, instructions) in batches {
+        connections.send(id, socket::ServerMessage::Instructions(instructions));
+    }
+    no_path
+}
+
+// Throw away everything learned since the last save, in every world.
+async fn restore_world(State(st): State<AppState>, identity: Identity) -> impl IntoResponse {
+    let config = st.config().await;
+    let path = &config.storage.worlds;
+    let mut worlds = st.worlds.write().await;
+    match Worlds::load(path) {
+        Ok(saved) => *worlds = saved,
+        Err(e) => {
             return (
-                StatusCode::NOT_FOUND,
+                StatusCode::INTERNAL_SERVER_ERROR,
                 Json(Message {
-                    text: "Turtle not found".to_string(),
+                    text: format!("Failed to load {}: {}", path, e),
                 }),
             )
                 .into_response();
         }
-    };
+    }
+    let mut jobs = st.jobs.write().await;
+    jobs.recount(&worlds);
+    jobs.publish_changes();
+    drop(jobs);
+    let blocks = worlds.block_count();
+    info!("Restored {} blocks from {}", blocks, path);
+    st.audit
+        .write()
+        .await
+        .record(&identity, Action::WorldRestored { blocks }, None, None);
 
-    (StatusCode::OK, axum::Json(instructions)).into_response()
+    StatusCode::OK.into_response()
 }
 
-async fn block_update(
+#[derive(Serialize)]
+struct ConfigReloaded {
+    /// Changed settings that only take effect after a restart.
+    needs_restart: Vec<&'static str>,
+}
+
+async fn reload_config(State(st): State<AppState>, identity: Identity) -> impl IntoResponse {
+    match config::reload(&st, &identity).await {
+        Ok(needs_restart) => Json(ConfigReloaded { needs_restart }).into_response(),
+        Err(text) => (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    }
+}
+
+async fn query_audit(
     State(st): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<StatusUpdate>,
+    Query(query): Query<AuditQuery>,
 ) -> impl IntoResponse {
-    let auth = headers.get("Authorization").unwrap().to_str().unwrap();
-    if !key_is_valid(auth) {
+    // The log is read on the blocking pool, without holding its lock
+    let file = st.audit.read().await.reader();
+    let found = match file {
+        Ok(file) => tokio::task::spawn_blocking(move || audit::search(file, &query))
+            .await
+            .expect("Searching the audit log panicked"),
+        Err(text) => Err(text),
+    };
+    match found {
+        Ok(entries) => Json(entries).into_response(),
+        Err(text) => (StatusCode::BAD_REQUEST, Json(Message { text })).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct TurtleInfo {
+    id: u32,
+    name: String,
+    position: Point3D,
+    facing: u8,
+    /// A number, `"unlimited"`, or null if the turtle hasn't said.
+    fuel: Option<Fuel>,
+    world: WorldId,
+    home: Point3D,
+    home_world: WorldId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hold: Option<Hold>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job: Option<JobId>,
+    connected: bool,
+    /// Seconds since the turtle was last heard from.
+    last_seen_secs: u64,
+    /// Heard from within `heartbeat.offline_after_secs`.
+    online: bool,
+}
+
+async fn list_turtles(State(st): State<AppState>) -> impl IntoResponse {
+    let offline_after = st.config().await.heartbeat.offline_after();
+    let jobs = st.jobs.read().await;
+    let turtles = st.turtles.read().await;
+    let connections = st.connections.read().await;
+    let fleet: Vec<TurtleInfo> = turtles
+        .iter()
+        .map(|t| TurtleInfo {
+            id: t.id(),
+            name: t.name().to_string(),
+            position: t.position(),
+            facing: t.facing(),
+            fuel: t.fuel(),
+            world: t.world().clone(),
+            home: t.home().1,
+            home_world: t.home().0.clone(),
+            hold: t.hold(),
+            job: jobs
+                .iter()
+                .find(|j| {
+                    j.assigned_to == Some(t.id())
+                        && matches!(
+                            j.status,
+                            JobStatus::Pending | JobStatus::InProgress | JobStatus::Paused
+                        )
+                })
+                .map(|j| j.id),
+            connected: connections.is_connected(t.id()),
+            last_seen_secs: t.last_heartbeat().elapsed().as_secs(),
+            online: t.last_heartbeat().elapsed() <= offline_after,
+        })
+        .collect();
+
+    Json(fleet)
+}
+
+async fn list_jobs(State(st): State<AppState>) -> impl IntoResponse {
+    // Serialized under the lock rather than cloning every job's targets
+    let jobs = st.jobs.read().await;
+    let body = serde_json::to_vec(&jobs.iter().collect::<Vec<&Job>>()).unwrap();
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/json")],
+        body,
+    )
+}
+
+async fn verify_job(State(st): State<AppState>, Path(id): Path<JobId>) -> impl IntoResponse {
+    let worlds = st.worlds.read().await;
+    let jobs = st.jobs.read().await;
+    let Some(job) = jobs.get(id) else {
         return (
-            StatusCode::UNAUTHORIZED,
+            StatusCode::NOT_FOUND,
             Json(Message {
-                text: "Invalid secret key".to_string(),
+                text: "Job not found".to_string(),
             }),
         )
             .into_response();
+    };
+    let check = job.verify(worlds.get(&job.world));
+    match check {
+        Some(check) => (StatusCode::OK, Json(check)).into_response(),
+        None => (
+            StatusCode::BAD_REQUEST,
+            Json(Message {
+                text: "Job has no blocks to verify".to_string(),
+            }),
+        )
+            .into_response(),
     }
-    let mut world = st.world.write().await; // write lock for concurrent writers
-    for block in payload.blocks {
-        world.set_block(block);
-    }
-
-    StatusCode::OK.into_response()
 }
 
 async fn path_request(
     State(app): State<AppState>,
-    headers: HeaderMap,
+    identity: Identity,
     Json(payload): Json<PathRequest>,
 ) -> impl IntoResponse {
-    let auth = headers.get("Authorization").unwrap().to_str().unwrap();
-    if !key_is_valid(auth) {
+    if payload.start == payload.goal {
+        let instructions = Instructions::new();
+        return (StatusCode::OK, axum::Json(instructions)).into_response();
+    }
+    if let Err(wait) = app.path_limiter.write().await.check(&identity.to_string()) {
+        return limit::too_many_requests(wait);
+    }
+    let padding = app.config().await.pathfinder.padding;
+    if turtle::search_bounds(payload.start, payload.goal, padding).is_none() {
         return (
-            StatusCode::UNAUTHORIZED,
+            StatusCode::BAD_REQUEST,
             Json(Message {
-                text: "Invalid secret key".to_string(),
+                text: "Path search out of range".to_string(),
             }),
         )
             .into_response();
     }
-    if payload.start == payload.goal {
-        let instructions = Instructions::new();
-        return (StatusCode::OK, axum::Json(instructions)).into_response();
-    }
 
-    let padding: u32 = 2;
-    let can_dig: bool = true;
-
-    let world = app.world.read().await;
+    // Turtles path in the world they're in unless they say otherwise
+    let world_id = match (payload.world, &identity) {
+        (Some(world), _) => world,
+        (None, Identity::Turtle(id)) => app
+            .turtles
+            .read()
+            .await
+            .get_turtle(*id)
+            .map(|t| t.world().clone())
+            .unwrap_or_default(),
+        (None, Identity::User { .. } | Identity::System) => WorldId::default(),
+    };
     let t0 = std::time::Instant::now();
-    match world.get_path(payload.start, payload.goal, padding, can_dig) {
-        Some(path) => {
-            let mut instructions = Instructions::new();
+    let mut instructions = Instructions::new();
+    match find_path(&app, &world_id, payload.start, payload.goal).await {
+        Ok(path) => {
             instructions.steps = path;
-            let dt = t0.elapsed();
-            println!(
+            debug!(
                 "Handled request with {} moves in {:.3?}",
                 instructions.steps.len(),
-                dt
+                t0.elapsed()
             );
-            (StatusCode::OK, axum::Json(instructions)).into_response()
         }
-        None => {
-            let mut instructions = Instructions::new();
-            instructions.steps.push("Error: No path found".to_string());
-            let dt = t0.elapsed();
-            println!("No path found (took {:.3?})", dt);
-            (StatusCode::OK, axum::Json(instructions)).into_response()
+        Err(error) => {
+            debug!("{} (took {:.3?})", error, t0.elapsed());
+            instructions.steps.push(format!("Error: {}", error));
+        }
+    }
+    (StatusCode::OK, axum::Json(instructions)).into_response()
+}
+
+// Plan a path in one world. The grid is copied out of the cache under the
+// world lock, then searched without any lock on the blocking pool, and only
+// `max_path_searches` at once.
+async fn find_path(
+    st: &AppState,
+    world_id: &WorldId,
+    start: Point3D,
+    goal: Point3D,
+) -> Result<Vec<String>, &'static str> {
+    let config = st.config().await;
+    let grid = {
+        let worlds = st.worlds.read().await;
+        worlds.get(world_id).path_grid(
+            start,
+            goal,
+            &config.pathfinder,
+            config.world.height(&world_id.dimension),
+        )
+    };
+    let (grid, goal) = grid?;
+    let permit = st
+        .path_permits
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("Path search semaphore is never closed");
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        turtle::find_moves(&grid, start, goal)
+    })
+    .await
+    .unwrap_or(None)
+    .ok_or("No path found")
+}
+
+// Compact scanner upload: every block is [x, y, z, palette index].
+#[derive(Deserialize)]
+struct ScanUpload {
+    palette: Vec<String>,
+    blocks: Vec<[i32; 4]>,
+    /// Coordinates are offsets from `origin`, or from the turtle's last
+    /// reported position if there's no origin.
+    #[serde(default)]
+    relative: bool,
+    origin: Option<Point3D>,
+    /// Cube radius scanned around `origin`. Cells inside it that aren't
+    /// listed are recorded as air, since scanners leave air out.
+    radius: Option<i32>,
+    /// Defaults to the world the turtle last reported being in.
+    world: Option<WorldId>,
+}
+
+async fn scan_upload(
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+    Json(payload): Json<ScanUpload>,
+) -> impl IntoResponse {
+    let turtle = st
+        .turtles
+        .read()
+        .await
+        .get_turtle(turtle_id)
+        .map(|t| (t.position(), t.world().clone()));
+    let world_id = payload
+        .world
+        .or_else(|| turtle.as_ref().map(|(_, world)| world.clone()))
+        .unwrap_or_default();
+    let origin = match (payload.relative, payload.origin) {
+        (false, _) => Point3D::new(0, 0, 0),
+        (true, Some(origin)) => origin,
+        (true, None) => match turtle {
+            Some((position, _)) => position,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(Message {
+                        text: "Relative scan needs an origin or a registered turtle".to_string(),
+                    }),
+                )
+                    .into_response();
+            }
+        },
+    };
+
+    // Build the whole batch before taking the write lock
+    let mut blocks = Vec::with_capacity(payload.blocks.len());
+    let mut listed = std::collections::HashSet::new();
+    for [x, y, z, i] in payload.blocks {
+        let Some(name) = payload.palette.get(i as usize) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: format!("Palette index {} out of range", i),
+                }),
+            )
+                .into_response();
+        };
+        let (Some(px), Some(py), Some(pz)) = (
+            origin.x.checked_add(x),
+            origin.y.checked_add(y),
+            origin.z.checked_add(z),
+        ) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: format!("Block at {}, {}, {} is out of range", x, y, z),
+                }),
+            )
+                .into_response();
+        };
+        let position = Point3D::new(px, py, pz);
+        listed.insert(position);
+        blocks.push(Block::new(position, name.clone()));
+    }
+    if let Some(r) = payload.radius {
+        // An absolute scan still needs to say where the scanner was
+        let Some(center) = payload.relative.then_some(origin).or(payload.origin) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: "Scan radius needs an origin".to_string(),
+                }),
+            )
+                .into_response();
+        };
+        let max_radius = st.config().await.limits.max_scan_radius;
+        if !(0..=max_radius).contains(&r) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: format!("Scan radius must be from 0 to {}", max_radius),
+                }),
+            )
+                .into_response();
+        }
+        let Some((min, max)) = scan_box(center, r) else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(Message {
+                    text: "Scan box is out of range".to_string(),
+                }),
+            )
+                .into_response();
+        };
+        for p in plan::box_cells(min, max) {
+            if p != center && !listed.contains(&p) {
+                blocks.push(Block::new(p, "a".to_string()));
+            }
         }
     }
+    let count = blocks.len();
+    let region = audit::region_of(blocks.iter().map(Block::position));
+
+    let t0 = std::time::Instant::now();
+    let mut worlds = st.worlds.write().await;
+    let world = worlds.get_mut(&world_id);
+    let changed = world.set_blocks(blocks);
+    info!(
+        "Applied scan of {} blocks in {} in {:.3?}",
+        count,
+        world_id,
+        t0.elapsed()
+    );
+    st.audit.write().await.record(
+        &Identity::Turtle(turtle_id),
+        Action::ScanApplied { count },
+        Some(&world_id),
+        region,
+    );
+    let mut jobs = st.jobs.write().await;
+    jobs.blocks_changed(&world_id, world, &changed);
+    jobs.publish_changes();
+    if !changed.is_empty() {
+        st.events.publish(Event::BlocksChanged {
+            world: world_id.clone(),
+            blocks: changed,
+        });
+    }
+
+    StatusCode::OK.into_response()
+}
+
+// The corners of the cube `r` around `center`, if its coordinates and cell
+// count fit.
+fn scan_box(center: Point3D, r: i32) -> Option<(Point3D, Point3D)> {
+    let side = 2 * r as u64 + 1;
+    side.checked_mul(side)?.checked_mul(side)?;
+    let min = Point3D::new(
+        center.x.checked_sub(r)?,
+        center.y.checked_sub(r)?,
+        center.z.checked_sub(r)?,
+    );
+    let max = Point3D::new(
+        center.x.checked_add(r)?,
+        center.y.checked_add(r)?,
+        center.z.checked_add(r)?,
+    );
+    Some((min, max))
+}
+
+#[derive(Deserialize)]
+struct InventoryUpdate {
+    inventory: Vec<Item>,
+}
+
+// Store and audit a turtle's inventory. Returns false if it isn't registered.
+async fn record_inventory(st: &AppState, turtle_id: u32, inventory: Vec<Item>) -> bool {
+    let slots = inventory.len();
+    if !st.turtles.write().await.set_inventory(turtle_id, inventory) {
+        return false;
+    }
+    st.audit.write().await.record(
+        &Identity::Turtle(turtle_id),
+        Action::InventoryUpdated { slots },
+        None,
+        None,
+    );
+    true
+}
+
+async fn inventory_update(
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+    Json(payload): Json<InventoryUpdate>,
+) -> impl IntoResponse {
+    if !record_inventory(&st, turtle_id, payload.inventory).await {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(Message {
+                text: "Turtle not found".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    StatusCode::OK.into_response()
 }
 
 #[derive(Deserialize)]
@@ -213,6 +1388,17 @@ struct StatusUpdate {
     blocks: Vec<Block>,
     position: Point3D,
     rotation: u8,
+    /// Left out by clients that don't track it.
+    fuel: Option<Fuel>,
+    /// Where the turtle is now, if it moved to another world or this is its
+    /// first report.
+    world: Option<WorldId>,
+    /// The batch and step the turtle just finished, if it was running one.
+    batch: Option<u64>,
+    instruction_index: Option<u32>,
+    /// Set while a counted move is still going: how many of its moves are
+    /// made. The step is only finished once a report comes without it.
+    moved: Option<u32>,
 }
 
 // most likely temporary for now for testing, maybe keep if manually
@@ -221,20 +1407,24 @@ struct StatusUpdate {
 struct PathRequest {
     start: Point3D,
     goal: Point3D,
+    world: Option<WorldId>,
 }
 
-async fn save_once(app_state: &AppState, path: &str) {
-    // Snapshot JSON while holding a read lock, then write atomically.
-    let world = app_state.world.read().await;
-    world.save_world(path).unwrap();
+async fn save_once(app_state: &AppState, dir: &str) {
+    let worlds = app_state.worlds.read().await;
+    let started = std::time::Instant::now();
+    worlds.save(dir).unwrap();
+    METRICS
+        .save_seconds
+        .observe(started.elapsed().as_secs_f64());
 }
 
 async fn start_periodic_saves(app_state: AppState, path: String, every: Duration) {
     let mut ticker = tokio::time::interval(every);
-    println!("Starting periodic saves every {:?} to {}", every, path);
+    info!("Starting periodic saves every {:?} to {}", every, path);
     loop {
         ticker.tick().await;
         save_once(&app_state, &path).await;
-        println!("Saved world to {}", path);
+        debug!("Saved worlds to {}", path);
     }
 }
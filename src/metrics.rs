@@ -0,0 +1,232 @@
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder, exponential_buckets,
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::job::JobStatus;
+use crate::state::AppState;
+
+/// Every metric the server exports. Global, since the pathfinder and the
+/// locks record into it from places that have no `AppState`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_seconds: HistogramVec,
+    pub path_search_seconds: Histogram,
+    pub path_search_nodes: Histogram,
+    pub save_seconds: Histogram,
+    lock_wait_seconds: HistogramVec,
+    world_blocks: IntGaugeVec,
+    turtles: IntGaugeVec,
+    jobs: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("turtle".to_string()), None)
+            .expect("The metric prefix is valid");
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to handle a request, up to its response headers",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let path_search_seconds = Histogram::with_opts(
+            HistogramOpts::new("path_search_duration_seconds", "Time spent in A* searches")
+                .buckets(exponential_buckets(0.0001, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let path_search_nodes = Histogram::with_opts(
+            HistogramOpts::new(
+                "path_search_expanded_nodes",
+                "Grid cells expanded by each A* search",
+            )
+            .buckets(exponential_buckets(10.0, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let save_seconds = Histogram::with_opts(HistogramOpts::new(
+            "world_save_duration_seconds",
+            "Time to save every world to disk",
+        ))
+        .unwrap();
+        let lock_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "lock_wait_seconds",
+                "Time spent waiting to take a shared state lock",
+            )
+            .buckets(exponential_buckets(0.000001, 4.0, 11).unwrap()),
+            &["lock", "mode"],
+        )
+        .unwrap();
+        let world_blocks = IntGaugeVec::new(
+            Opts::new("world_blocks", "Blocks cached per world"),
+            &["world"],
+        )
+        .unwrap();
+        let turtles = IntGaugeVec::new(
+            Opts::new(
+                "turtles",
+                "Turtles heard from within heartbeat.offline_after_secs (active) or not (stale)",
+            ),
+            &["state"],
+        )
+        .unwrap();
+        let jobs = IntGaugeVec::new(Opts::new("jobs", "Jobs by status"), &["status"]).unwrap();
+
+        for metric in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(request_seconds.clone()),
+            Box::new(path_search_seconds.clone()),
+            Box::new(path_search_nodes.clone()),
+            Box::new(save_seconds.clone()),
+            Box::new(lock_wait_seconds.clone()),
+            Box::new(world_blocks.clone()),
+            Box::new(turtles.clone()),
+            Box::new(jobs.clone()),
+        ] {
+            registry.register(metric).expect("Metric names are unique");
+        }
+        Metrics {
+            registry,
+            requests,
+            request_seconds,
+            path_search_seconds,
+            path_search_nodes,
+            save_seconds,
+            lock_wait_seconds,
+            world_blocks,
+            turtles,
+            jobs,
+        }
+    }
+}
+
+/// Layer counting and timing every request by its route. Requests that
+/// don't match a route share one label, so scanners can't add new series.
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched".to_string(), |p| p.as_str().to_string());
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let response = next.run(req).await;
+    METRICS
+        .request_seconds
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    METRICS
+        .requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+/// `GET /metrics`: everything above in the Prometheus text format.
+pub async fn export(State(st): State<AppState>) -> impl IntoResponse {
+    let offline_after = st.config().await.heartbeat.offline_after();
+    {
+        let worlds = st.worlds.read().await;
+        METRICS.world_blocks.reset();
+        for (id, world) in worlds.iter() {
+            METRICS
+                .world_blocks
+                .with_label_values(&[&id.to_string()])
+                .set(world.len() as i64);
+        }
+    }
+    {
+        let jobs = st.jobs.read().await;
+        for status in [
+            JobStatus::Pending,
+            JobStatus::InProgress,
+            JobStatus::Paused,
+            JobStatus::Done,
+            JobStatus::Failed,
+            JobStatus::Cancelled,
+        ] {
+            let count = jobs.iter().filter(|j| j.status == status).count();
+            METRICS
+                .jobs
+                .with_label_values(&[&format!("{:?}", status)])
+                .set(count as i64);
+        }
+    }
+    {
+        let turtles = st.turtles.read().await;
+        let active = turtles
+            .iter()
+            .filter(|t| t.last_heartbeat().elapsed() <= offline_after)
+            .count();
+        let stale = turtles.iter().count() - active;
+        METRICS
+            .turtles
+            .with_label_values(&["active"])
+            .set(active as i64);
+        METRICS
+            .turtles
+            .with_label_values(&["stale"])
+            .set(stale as i64);
+    }
+
+    let mut body = Vec::new();
+    match TextEncoder::new().encode(&METRICS.registry.gather(), &mut body) {
+        Ok(()) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// An `RwLock` that records how long callers wait to take it.
+pub struct TimedRwLock<T> {
+    name: &'static str,
+    lock: RwLock<T>,
+}
+
+impl<T> TimedRwLock<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        TimedRwLock {
+            name,
+            lock: RwLock::new(value),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.lock.read().await;
+        self.waited("read", start);
+        guard
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.lock.write().await;
+        self.waited("write", start);
+        guard
+    }
+
+    fn waited(&self, mode: &str, start: Instant) {
+        METRICS
+            .lock_wait_seconds
+            .with_label_values(&[self.name, mode])
+            .observe(start.elapsed().as_secs_f64());
+    }
+}
@@ -1,9 +1,12 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::time::Instant;
 
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+use crate::metrics::METRICS;
+
 // Core types
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Encode, Decode)]
@@ -17,11 +20,18 @@ impl Point3D {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         Point3D { x, y, z }
     }
+    /// This point moved `n` times along `d`.
+    #[inline]
+    pub fn offset(&self, d: Point3D, n: i32) -> Point3D {
+        Point3D::new(self.x + d.x * n, self.y + d.y * n, self.z + d.z * n)
+    }
+    /// Saturates at `u32::MAX` for points at opposite ends of the world.
     #[inline]
     pub fn manhattan_distance(&self, other: &Point3D) -> u32 {
-        (self.x - other.x).abs() as u32
-            + (self.y - other.y).abs() as u32
-            + (self.z - other.z).abs() as u32
+        self.x
+            .abs_diff(other.x)
+            .saturating_add(self.y.abs_diff(other.y))
+            .saturating_add(self.z.abs_diff(other.z))
     }
 }
 
@@ -61,9 +71,9 @@ impl Grid {
     /// Fills with `default_cost`.
     pub fn new(min: Point3D, max: Point3D, default_cost: u16) -> Self {
         assert!(max.x >= min.x && max.y >= min.y && max.z >= min.z);
-        let nx = (max.x - min.x + 1) as usize;
-        let ny = (max.y - min.y + 1) as usize;
-        let nz = (max.z - min.z + 1) as usize;
+        let nx = max.x.abs_diff(min.x) as usize + 1;
+        let ny = max.y.abs_diff(min.y) as usize + 1;
+        let nz = max.z.abs_diff(min.z) as usize + 1;
         let len = nx
             .checked_mul(ny)
             .and_then(|v| v.checked_mul(nz))
@@ -79,12 +89,12 @@ impl Grid {
 
     #[inline]
     pub fn in_bounds(&self, p: Point3D) -> bool {
-        let nx = self.dims.0 as i32;
-        let ny = self.dims.1 as i32;
-        let nz = self.dims.2 as i32;
-        let ix = p.x - self.min.x;
-        let iy = p.y - self.min.y;
-        let iz = p.z - self.min.z;
+        let nx = self.dims.0 as i64;
+        let ny = self.dims.1 as i64;
+        let nz = self.dims.2 as i64;
+        let ix = p.x as i64 - self.min.x as i64;
+        let iy = p.y as i64 - self.min.y as i64;
+        let iz = p.z as i64 - self.min.z as i64;
         ix >= 0 && ix < nx && iy >= 0 && iy < ny && iz >= 0 && iz < nz
     }
 
@@ -94,9 +104,9 @@ impl Grid {
         if !self.in_bounds(p) {
             return None;
         }
-        let ix = (p.x - self.min.x) as usize;
-        let iy = (p.y - self.min.y) as usize;
-        let iz = (p.z - self.min.z) as usize;
+        let ix = p.x.abs_diff(self.min.x) as usize;
+        let iy = p.y.abs_diff(self.min.y) as usize;
+        let iz = p.z.abs_diff(self.min.z) as usize;
         Some((ix * self.dims.1 + iy) * self.dims.2 + iz)
     }
 
@@ -109,9 +119,9 @@ impl Grid {
         let iy = rem / nz;
         let iz = rem % nz;
         Point3D {
-            x: self.min.x + ix as i32,
-            y: self.min.y + iy as i32,
-            z: self.min.z + iz as i32,
+            x: self.min.x.wrapping_add_unsigned(ix as u32),
+            y: self.min.y.wrapping_add_unsigned(iy as u32),
+            z: self.min.z.wrapping_add_unsigned(iz as u32),
         }
     }
 
@@ -133,7 +143,25 @@ impl Grid {
     }
 }
 
+/// A* over the grid, recording its duration and expanded nodes in the
+/// metrics.
 pub fn astar_find_path(grid: &Grid, start: Point3D, goal: Point3D) -> Option<Vec<Point3D>> {
+    let started = Instant::now();
+    let mut expanded = 0u64;
+    let path = astar_search(grid, start, goal, &mut expanded);
+    METRICS
+        .path_search_seconds
+        .observe(started.elapsed().as_secs_f64());
+    METRICS.path_search_nodes.observe(expanded as f64);
+    path
+}
+
+fn astar_search(
+    grid: &Grid,
+    start: Point3D,
+    goal: Point3D,
+    expanded: &mut u64,
+) -> Option<Vec<Point3D>> {
     let start_idx = grid.idx(start)?;
     let goal_idx = grid.idx(goal)?;
 
@@ -176,6 +204,7 @@ pub fn astar_find_path(grid: &Grid, start: Point3D, goal: Point3D) -> Option<Vec
         if g != g_score[current_idx] {
             continue;
         }
+        *expanded += 1;
 
         if current_idx == goal_idx {
             return Some(reconstruct_path(grid, &parent, current_idx));
@@ -184,7 +213,14 @@ pub fn astar_find_path(grid: &Grid, start: Point3D, goal: Point3D) -> Option<Vec
         let current_pt = grid.point(current_idx);
 
         for d in &DIRS {
-            let nb_pt = Point3D::new(current_pt.x + d.x, current_pt.y + d.y, current_pt.z + d.z);
+            let (Some(x), Some(y), Some(z)) = (
+                current_pt.x.checked_add(d.x),
+                current_pt.y.checked_add(d.y),
+                current_pt.z.checked_add(d.z),
+            ) else {
+                continue;
+            };
+            let nb_pt = Point3D::new(x, y, z);
             let Some(nb_idx) = grid.idx(nb_pt) else {
                 continue;
             };
@@ -269,11 +305,11 @@ pub fn path_to_moves(grid: &Grid, path: &[Point3D]) -> Result<Vec<String>, Strin
                 ("south", "facesouth")
             };
 
-            if let Some(cost) = grid.cost_point(b) {
-                if cost > 1 {
-                    moves.push(face_word.to_string()); // face first
-                    moves.push("dig".to_string()); // then dig
-                }
+            if let Some(cost) = grid.cost_point(b)
+                && cost > 1
+            {
+                moves.push(face_word.to_string()); // face first
+                moves.push("dig".to_string()); // then dig
             }
             moves.push(dir_word.to_string()); // then move
         }
@@ -285,14 +321,224 @@ pub fn path_to_moves(grid: &Grid, path: &[Point3D]) -> Result<Vec<String>, Strin
                 ("down", "digdown")
             };
 
-            if let Some(cost) = grid.cost_point(b) {
-                if cost > 1 {
-                    moves.push(dig_word.to_string()); // digup/digdown first
-                }
+            if let Some(cost) = grid.cost_point(b)
+                && cost > 1
+            {
+                moves.push(dig_word.to_string()); // digup/digdown first
             }
             moves.push(move_word.to_string()); // then move
         }
     }
 
-    Ok(moves)
+    Ok(run_length(moves))
+}
+
+// Words a count can follow. `forward` goes the way the turtle faces.
+const MOVE_WORDS: [&str; 7] = ["up", "down", "forward", "north", "east", "south", "west"];
+const LATERAL_WORDS: [&str; 4] = ["north", "east", "south", "west"];
+
+/// Collapse runs of the same move into one counted step. 37 `"east"`s become
+/// `"faceeast"` then `"forward 37"`, and 5 `"up"`s become `"up 5"`. Steps
+/// that are already counted merge too.
+pub fn run_length(moves: Vec<String>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::with_capacity(moves.len());
+    let mut run: Option<(&str, u32)> = None;
+    let mut i = 0;
+    while i < moves.len() {
+        let Some((word, count, used)) = next_move(&moves[i..]) else {
+            if let Some((w, n)) = run.take() {
+                push_counted(&mut out, w, n);
+            }
+            out.push(moves[i].clone());
+            i += 1;
+            continue;
+        };
+        i += used;
+        run = match run {
+            Some((w, n)) if w == word => Some((w, n.saturating_add(count))),
+            Some((w, n)) => {
+                push_counted(&mut out, w, n);
+                Some((word, count))
+            }
+            None => Some((word, count)),
+        };
+    }
+    if let Some((w, n)) = run {
+        push_counted(&mut out, w, n);
+    }
+    out
+}
+
+/// The move at the start of `steps` as an absolute direction or up/down,
+/// its count and how many steps it took up. A `forward` only counts if it
+/// comes right after the step that sets the facing.
+fn next_move(steps: &[String]) -> Option<(&str, u32, usize)> {
+    if let Some(direction) = steps[0].strip_prefix("face")
+        && LATERAL_WORDS.contains(&direction)
+        && let Some(("forward", n)) = steps.get(1).map(|s| split_count(s))
+    {
+        return Some((direction, n, 2));
+    }
+    match split_count(&steps[0]) {
+        ("forward", _) => None,
+        (word, n) if MOVE_WORDS.contains(&word) => Some((word, n, 1)),
+        _ => None,
+    }
+}
+
+/// Split a step like `"forward 37"` into its word and repeat count. Steps
+/// without a count run once.
+pub fn split_count(step: &str) -> (&str, u32) {
+    if let Some((word, count)) = step.split_once(' ')
+        && MOVE_WORDS.contains(&word)
+        && let Ok(n) = count.parse()
+    {
+        return (word, n);
+    }
+    (step, 1)
+}
+
+fn push_counted(out: &mut Vec<String>, word: &str, n: u32) {
+    if n == 1 {
+        out.push(word.to_string());
+    } else if LATERAL_WORDS.contains(&word) {
+        out.push(format!("face{}", word));
+        out.push(format!("forward {}", n));
+    } else {
+        out.push(format!("{} {}", word, n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    // Back to one absolute move per step, following the facing like a
+    // turtle would
+    fn expand(encoded: &[String]) -> Vec<String> {
+        let mut facing = None;
+        let mut out = Vec::new();
+        for step in encoded {
+            let (word, count) = split_count(step);
+            if let Some(direction) = word.strip_prefix("face") {
+                facing = Some(direction.to_string());
+                continue;
+            }
+            let word = match word {
+                "forward" => facing.clone().expect("forward needs a facing"),
+                _ => word.to_string(),
+            };
+            if LATERAL_WORDS.contains(&word.as_str()) {
+                facing = Some(word.clone());
+            }
+            out.extend(std::iter::repeat_n(word, count as usize));
+        }
+        out
+    }
+
+    #[test]
+    fn run_length_of_nothing() {
+        assert!(run_length(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn single_moves_stay_as_they_are() {
+        let moves = steps(&["east", "up", "north", "down"]);
+        assert_eq!(run_length(moves.clone()), moves);
+    }
+
+    #[test]
+    fn lateral_runs_face_then_go_forward() {
+        assert_eq!(
+            run_length(steps(&["east"; 37])),
+            steps(&["faceeast", "forward 37"])
+        );
+    }
+
+    #[test]
+    fn vertical_runs_are_counted() {
+        assert_eq!(
+            run_length(steps(&["up", "up", "up", "down"])),
+            steps(&["up 3", "down"])
+        );
+    }
+
+    #[test]
+    fn other_steps_break_runs() {
+        let moves = steps(&[
+            "east", "east", "faceeast", "dig", "east", "east", "digup", "up", "up",
+        ]);
+        assert_eq!(
+            run_length(moves),
+            steps(&[
+                "faceeast",
+                "forward 2",
+                "faceeast",
+                "dig",
+                "faceeast",
+                "forward 2",
+                "digup",
+                "up 2",
+            ])
+        );
+    }
+
+    #[test]
+    fn encoded_runs_merge() {
+        let moves = steps(&["faceeast", "forward 3", "east", "up 2", "up"]);
+        assert_eq!(run_length(moves), steps(&["faceeast", "forward 4", "up 3"]));
+    }
+
+    #[test]
+    fn forward_without_a_facing_is_left_alone() {
+        let moves = steps(&["turnleft", "forward 3", "forward 2"]);
+        assert_eq!(run_length(moves.clone()), moves);
+    }
+
+    #[test]
+    fn run_length_round_trips() {
+        let moves = steps(&[
+            "east", "east", "east", "south", "up", "up", "west", "dig", "west", "north", "north",
+            "down",
+        ]);
+        let encoded = run_length(moves.clone());
+        assert!(encoded.len() < moves.len());
+        assert_eq!(expand(&encoded), expand(&moves));
+        assert_eq!(run_length(encoded.clone()), encoded);
+    }
+
+    #[test]
+    fn split_count_reads_counts() {
+        assert_eq!(split_count("forward 37"), ("forward", 37));
+        assert_eq!(split_count("up 1"), ("up", 1));
+        assert_eq!(split_count("east"), ("east", 1));
+    }
+
+    #[test]
+    fn split_count_leaves_other_steps_whole() {
+        assert_eq!(split_count("scan 8"), ("scan 8", 1));
+        assert_eq!(
+            split_count("select minecraft:dirt"),
+            ("select minecraft:dirt", 1)
+        );
+        assert_eq!(split_count("east far"), ("east far", 1));
+        assert_eq!(split_count(""), ("", 1));
+    }
+
+    #[test]
+    fn manhattan_distance_across_the_whole_range() {
+        let a = Point3D::new(i32::MIN, 0, 0);
+        let b = Point3D::new(i32::MAX, 0, 0);
+        assert_eq!(a.manhattan_distance(&b), u32::MAX);
+        let c = Point3D::new(i32::MAX, i32::MAX, 0);
+        assert_eq!(a.manhattan_distance(&c), u32::MAX);
+        assert_eq!(
+            Point3D::new(-3, 4, 0).manhattan_distance(&Point3D::new(2, 0, 1)),
+            10
+        );
+    }
 }
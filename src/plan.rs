@@ -0,0 +1,1052 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::estimate::INVENTORY_CAPACITY;
+use crate::job::{Direction3, JobKind};
+use crate::pathfinder::Point3D;
+use crate::turtle::Block;
+
+// Expands a job into the instruction stream a turtle runs once it has reached
+// the job's starting point. Uses the same words as `path_to_moves`.
+pub fn expand(kind: &JobKind) -> Vec<String> {
+    match kind {
+        JobKind::Goto { .. } => Vec::new(),
+        JobKind::Quarry {
+            top_corner,
+            bottom_corner,
+            ..
+        } => quarry(*top_corner, *bottom_corner),
+        JobKind::StripMine {
+            start,
+            direction,
+            length,
+            spacing,
+            lanes,
+        } => strip_mine(*start, *direction, *length, *spacing, *lanes),
+        JobKind::Tunnel {
+            start,
+            direction,
+            length,
+            width,
+            height,
+            torch_spacing,
+            floor,
+        } => tunnel(
+            *start,
+            *direction,
+            *length,
+            (*width, *height),
+            *torch_spacing,
+            floor.as_deref(),
+        ),
+        JobKind::Staircase {
+            start,
+            direction,
+            target_y,
+        } => staircase(*start, *direction, *target_y),
+        JobKind::TreeFarm {
+            top_corner,
+            bottom_corner,
+            spacing,
+            sapling,
+            chest,
+            ..
+        } => tree_farm(*top_corner, *bottom_corner, *spacing, sapling, *chest),
+        JobKind::CropFarm {
+            top_corner,
+            bottom_corner,
+            seed,
+            chest,
+            ..
+        } => crop_farm(*top_corner, *bottom_corner, seed, *chest),
+        // These need the cache to pick their targets, see `Job::plan`
+        JobKind::Build { .. }
+        | JobKind::Explore { .. }
+        | JobKind::Fill { .. }
+        | JobKind::Flatten { .. }
+        | JobKind::Bridge { .. } => Vec::new(),
+    }
+}
+
+/// Tracks where the turtle will be while instructions are appended.
+pub struct Planner {
+    pub position: Point3D,
+    pub steps: Vec<String>,
+}
+
+impl Planner {
+    pub fn new(start: Point3D) -> Self {
+        Planner {
+            position: start,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Dig into and move onto an adjacent cell.
+    pub fn step(&mut self, to: Point3D) {
+        let dx = to.x - self.position.x;
+        let dy = to.y - self.position.y;
+        let dz = to.z - self.position.z;
+        let (dig, mv) = match (dx, dy, dz) {
+            (1, 0, 0) => (Some("faceeast"), "east"),
+            (-1, 0, 0) => (Some("facewest"), "west"),
+            (0, 0, 1) => (Some("facesouth"), "south"),
+            (0, 0, -1) => (Some("facenorth"), "north"),
+            (0, 1, 0) => (None, "up"),
+            (0, -1, 0) => (None, "down"),
+            _ => panic!("non-adjacent step from {:?} to {:?}", self.position, to),
+        };
+        match dig {
+            Some(face) => {
+                self.push(face);
+                self.push("dig");
+            }
+            None if dy == 1 => self.push("digup"),
+            None => self.push("digdown"),
+        }
+        self.push(mv);
+        self.position = to;
+    }
+
+    /// Dig a straight line to `to`, one axis at a time (y, then x, then z).
+    pub fn walk(&mut self, to: Point3D) {
+        while self.position.y != to.y {
+            let dy = (to.y - self.position.y).signum();
+            self.step(Point3D::new(
+                self.position.x,
+                self.position.y + dy,
+                self.position.z,
+            ));
+        }
+        while self.position.x != to.x {
+            let dx = (to.x - self.position.x).signum();
+            self.step(Point3D::new(
+                self.position.x + dx,
+                self.position.y,
+                self.position.z,
+            ));
+        }
+        while self.position.z != to.z {
+            let dz = (to.z - self.position.z).signum();
+            self.step(Point3D::new(
+                self.position.x,
+                self.position.y,
+                self.position.z + dz,
+            ));
+        }
+    }
+
+    pub fn push(&mut self, step: &str) {
+        self.steps.push(step.to_string());
+    }
+
+    /// Select the first inventory slot holding `item`.
+    pub fn select(&mut self, item: &str) {
+        self.steps.push(format!("select {}", item));
+    }
+
+    /// Turn to face along a horizontal offset.
+    pub fn face(&mut self, d: Point3D) {
+        match (d.x, d.z) {
+            (1, _) => self.push("faceeast"),
+            (-1, _) => self.push("facewest"),
+            (_, 1) => self.push("facesouth"),
+            _ => self.push("facenorth"),
+        }
+    }
+}
+
+/// Smallest and largest corner of the box spanned by two points.
+pub fn bounds(a: Point3D, b: Point3D) -> (Point3D, Point3D) {
+    (
+        Point3D::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+        Point3D::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+    )
+}
+
+/// Visit every cell of one layer in a serpentine, starting from whichever
+/// corner the planner is standing on.
+pub fn sweep_layer(plan: &mut Planner, min: Point3D, max: Point3D) {
+    let y = plan.position.y;
+    let xs: Vec<i32> = if plan.position.x == max.x {
+        (min.x..=max.x).rev().collect()
+    } else {
+        (min.x..=max.x).collect()
+    };
+    let mut forward = plan.position.z != max.z;
+    for x in xs {
+        let zs: Vec<i32> = if forward {
+            (min.z..=max.z).collect()
+        } else {
+            (min.z..=max.z).rev().collect()
+        };
+        for z in zs {
+            plan.walk(Point3D::new(x, y, z));
+        }
+        forward = !forward;
+    }
+}
+
+fn quarry(top_corner: Point3D, bottom_corner: Point3D) -> Vec<String> {
+    let (min, max) = bounds(top_corner, bottom_corner);
+    let mut plan = Planner::new(top_corner);
+    // Snap to the top layer in case the corners were given the other way up
+    plan.walk(Point3D::new(top_corner.x, max.y, top_corner.z));
+    loop {
+        sweep_layer(&mut plan, min, max);
+        if plan.position.y == min.y {
+            break;
+        }
+        let below = Point3D::new(plan.position.x, plan.position.y - 1, plan.position.z);
+        plan.step(below);
+    }
+    plan.steps
+}
+
+fn strip_mine(
+    start: Point3D,
+    direction: Direction3,
+    length: u32,
+    spacing: u32,
+    lanes: u32,
+) -> Vec<String> {
+    let along = direction.offset();
+    let across = direction.perpendicular().offset();
+    let mut plan = Planner::new(start);
+    for lane in 0..lanes {
+        let sign = if lane % 2 == 0 { 1 } else { -1 };
+        for _ in 0..length {
+            plan.step(plan.position.offset(along, sign));
+            // Two-high tunnel so the player can walk it afterwards
+            plan.push("digup");
+        }
+        if lane + 1 < lanes {
+            for _ in 0..=spacing {
+                plan.step(plan.position.offset(across, 1));
+                plan.push("digup");
+            }
+        }
+    }
+    plan.steps
+}
+
+// The turtle walks the tunnel floor along `direction`. Each slice is cleared
+// column by column, snaking so no cell is walked twice. Blocks the turtle
+// digs or places are picked up by the `/update-block` report it sends after
+// every instruction, so the cache follows the tunnel as it is dug.
+fn tunnel(
+    start: Point3D,
+    direction: Direction3,
+    length: u32,
+    (width, height): (u32, u32),
+    torch_spacing: Option<u32>,
+    floor: Option<&str>,
+) -> Vec<String> {
+    let along = direction.offset();
+    let horizontal = direction.is_horizontal();
+    let across = direction.perpendicular().offset();
+    let up = if horizontal {
+        Point3D::new(0, 1, 0)
+    } else {
+        Point3D::new(0, 0, 1)
+    };
+    let width = width.max(1) as i32;
+    let height = height.max(1) as i32;
+    // Center the cross-section on the start point
+    let left = -(width - 1) / 2;
+
+    let mut plan = Planner::new(start);
+    let mut column = 0;
+    let mut rising = true;
+    let mut since_torch = 0;
+    for slice in 0..length as i32 {
+        let base = start.offset(along, slice + 1);
+        let columns: Vec<i32> = if column == 0 {
+            (0..width).collect()
+        } else {
+            (0..width).rev().collect()
+        };
+        for c in columns {
+            let rows: Vec<i32> = if rising {
+                (0..height).collect()
+            } else {
+                (0..height).rev().collect()
+            };
+            for r in rows {
+                let cell = base.offset(across, left + c).offset(up, r);
+                plan.walk(cell);
+                if horizontal
+                    && r == 0
+                    && let Some(block) = floor
+                {
+                    plan.select(block);
+                    plan.push("placedown");
+                }
+            }
+            column = c;
+            rising = !rising;
+        }
+        since_torch += 1;
+        // Slices alternate between ending at the floor and the ceiling, so a
+        // torch may land a slice late
+        if horizontal
+            && let Some(spacing) = torch_spacing
+            && since_torch >= spacing
+            && plan.position.y == start.y
+        {
+            // Stand the torch on the floor of the slice behind us
+            plan.face(Point3D::new(-along.x, 0, -along.z));
+            plan.select("minecraft:torch");
+            plan.push("place");
+            since_torch = 0;
+        }
+    }
+    plan.steps
+}
+
+// Each stair is one block along `direction` and one block towards
+// `target_y`, with two blocks of headroom above the tread.
+fn staircase(start: Point3D, direction: Direction3, target_y: i32) -> Vec<String> {
+    let along = direction.offset();
+    let mut plan = Planner::new(start);
+    while plan.position.y != target_y {
+        if target_y < plan.position.y {
+            plan.step(plan.position.offset(along, 1));
+            plan.push("digup");
+            plan.step(plan.position.offset(Point3D::new(0, -1, 0), 1));
+        } else {
+            plan.step(plan.position.offset(Point3D::new(0, 1, 0), 1));
+            plan.push("digup");
+            plan.step(plan.position.offset(along, 1));
+        }
+    }
+    // Clear headroom over the last tread too
+    plan.push("digup");
+    plan.steps
+}
+
+// Builds bottom-up one layer at a time, placing every block from directly
+// above. The turtle only ever flies through the layer above the one it is
+// placing, which nothing has been built in yet, so every cell is reachable.
+pub fn build(start: Point3D, targets: &[Block], storage: Option<Point3D>) -> Vec<String> {
+    let mut plan = Planner::new(start);
+    place_blocks(&mut plan, targets, storage);
+    plan.steps
+}
+
+/// Place every non-air target, bottom layer first. Air targets are cells the
+/// job digs out and are left to the caller.
+pub fn place_blocks(plan: &mut Planner, targets: &[Block], storage: Option<Point3D>) {
+    let mut layers: Vec<&Block> = targets.iter().filter(|b| b.is_solid()).collect();
+    layers.sort_by_key(|b| {
+        let p = b.position();
+        // Snake along z so neighbouring rows don't double back
+        let z = if p.x % 2 == 0 { p.z } else { -p.z };
+        (p.y, p.x, z)
+    });
+    let top = targets
+        .iter()
+        .map(|b| b.position().y)
+        .max()
+        .unwrap_or(plan.position.y);
+
+    let mut carried = if storage.is_some() {
+        0
+    } else {
+        // No storage, the turtle has to be loaded by hand
+        u32::MAX
+    };
+    let mut selected = "";
+    for block in layers {
+        if carried == 0
+            && let Some(chest) = storage
+        {
+            restock(plan, chest, top);
+            carried = INVENTORY_CAPACITY;
+            selected = "";
+        }
+        let p = block.position();
+        plan.walk(Point3D::new(p.x, p.y + 1, p.z));
+        if selected != block.block_type() {
+            plan.select(block.block_type());
+            selected = block.block_type();
+        }
+        plan.push("placedown");
+        carried -= 1;
+    }
+}
+
+// Fly over the structure to the chest, refill from it, and come back.
+fn restock(plan: &mut Planner, chest: Point3D, top: i32) {
+    let back = plan.position;
+    let clearance = (top + 2).max(chest.y + 1).max(back.y);
+    plan.walk(Point3D::new(back.x, clearance, back.z));
+    plan.walk(Point3D::new(chest.x, clearance, chest.z));
+    plan.walk(Point3D::new(chest.x, chest.y + 1, chest.z));
+    plan.push("restock");
+    plan.walk(Point3D::new(chest.x, clearance, chest.z));
+    plan.walk(Point3D::new(back.x, clearance, back.z));
+    plan.walk(back);
+}
+
+/// Every cell of the box spanned by two corners.
+pub fn box_cells(a: Point3D, b: Point3D) -> impl Iterator<Item = Point3D> {
+    let (min, max) = bounds(a, b);
+    (min.y..=max.y).flat_map(move |y| {
+        (min.x..=max.x).flat_map(move |x| (min.z..=max.z).map(move |z| Point3D::new(x, y, z)))
+    })
+}
+
+/// Cells of the box that aren't already known to be solid.
+pub fn fill_targets(
+    a: Point3D,
+    b: Point3D,
+    block_type: &str,
+    solid: &HashMap<Point3D, bool>,
+) -> Vec<Block> {
+    box_cells(a, b)
+        .filter(|p| !solid.get(p).copied().unwrap_or(false))
+        .map(|p| Block::new(p, block_type.to_string()))
+        .collect()
+}
+
+/// Cells above `target_y` to clear (as air) and known gaps at or below it to
+/// fill. Unknown cells above are assumed solid, unknown cells below are left.
+pub fn flatten_targets(
+    a: Point3D,
+    b: Point3D,
+    target_y: i32,
+    fill: &str,
+    solid: &HashMap<Point3D, bool>,
+) -> Vec<Block> {
+    box_cells(a, b)
+        .filter_map(|p| match (p.y > target_y, solid.get(&p).copied()) {
+            (true, Some(false)) => None,
+            (true, _) => Some(Block::new(p, "a".to_string())),
+            (false, Some(false)) => Some(Block::new(p, fill.to_string())),
+            (false, _) => None,
+        })
+        .collect()
+}
+
+// Fill every target cell of a box that may sit in existing ground.
+pub fn fill(
+    start: Point3D,
+    targets: &[Block],
+    storage: Option<Point3D>,
+    solid: &HashMap<Point3D, bool>,
+) -> Vec<String> {
+    let mut plan = Planner::new(start);
+    place_in_terrain(&mut plan, targets, storage, solid, HashSet::new());
+    plan.steps
+}
+
+/// Place every non-air target without digging anything `solid` says is there,
+/// other than the cells in `dug`, which the job has already cleared. Unlike
+/// `place_blocks`, the turtle can't fly over the site, since the ground
+/// around it is meant to stay. It only travels through cells that aren't
+/// known to be solid, and places each block from a free neighbour, from
+/// above if it can. Each layer is filled from the cells farthest from where
+/// the turtle came in, so it backs out the way it came. Targets it can't
+/// reach are left out, for the job's check to report.
+pub fn place_in_terrain(
+    plan: &mut Planner,
+    targets: &[Block],
+    storage: Option<Point3D>,
+    solid: &HashMap<Point3D, bool>,
+    mut dug: HashSet<Point3D>,
+) {
+    let entry = plan.position;
+    let mut layers: Vec<&Block> = targets.iter().filter(|b| b.is_solid()).collect();
+    if layers.is_empty() {
+        return;
+    }
+    layers.sort_by_key(|b| {
+        let p = b.position();
+        let z = if p.x % 2 == 0 { p.z } else { -p.z };
+        let from_entry = (p.x as i64 - entry.x as i64).abs() + (p.z as i64 - entry.z as i64).abs();
+        (p.y, std::cmp::Reverse(from_entry), p.x, z)
+    });
+    // The turtle is standing at the start, whatever the cache says
+    dug.insert(entry);
+    let (min, max) = targets
+        .iter()
+        .map(Block::position)
+        .chain(storage.map(|c| c.offset(NEIGHBOURS[2], 1)))
+        .fold((entry, entry), |(lo, hi), p| {
+            (bounds(lo, p).0, bounds(hi, p).1)
+        });
+    let mut site = Site {
+        solid,
+        dug,
+        placed: HashSet::new(),
+        min: Point3D::new(
+            min.x.saturating_sub(1),
+            min.y.saturating_sub(1),
+            min.z.saturating_sub(1),
+        ),
+        max: Point3D::new(
+            max.x.saturating_add(1),
+            max.y.saturating_add(1),
+            max.z.saturating_add(1),
+        ),
+        reach: None,
+    };
+
+    let mut carried = if storage.is_some() { 0 } else { u32::MAX };
+    let mut selected = "";
+    for block in layers {
+        if carried == 0
+            && let Some(chest) = storage
+        {
+            let above = chest.offset(NEIGHBOURS[2], 1);
+            if !site.travel(plan, &[above]) {
+                // Nothing more can be placed without blocks
+                return;
+            }
+            plan.push("restock");
+            carried = INVENTORY_CAPACITY;
+            selected = "";
+        }
+        let p = block.position();
+        // From above first, then the sides, then below
+        let spots: Vec<Point3D> = [2, 0, 1, 4, 5, 3]
+            .iter()
+            .map(|&i| p.offset(NEIGHBOURS[i], 1))
+            .filter(|&c| site.is_free(c))
+            .collect();
+        if !site.travel(plan, &spots) {
+            continue;
+        }
+        if selected != block.block_type() {
+            plan.select(block.block_type());
+            selected = block.block_type();
+        }
+        let at = plan.position;
+        match p.y - at.y {
+            -1 => plan.push("placedown"),
+            1 => plan.push("placeup"),
+            _ => {
+                plan.face(Point3D::new(p.x - at.x, 0, p.z - at.z));
+                plan.push("place");
+            }
+        }
+        site.placed.insert(p);
+        carried -= 1;
+    }
+}
+
+/// What `place_in_terrain` knows about the cells around the job.
+struct Site<'a> {
+    solid: &'a HashMap<Point3D, bool>,
+    dug: HashSet<Point3D>,
+    placed: HashSet<Point3D>,
+    /// Corners of the box the turtle may travel in.
+    min: Point3D,
+    max: Point3D,
+    /// Every cell the turtle could reach the last time a search failed.
+    /// Placing blocks only ever closes cells off, so nothing outside it can
+    /// be reached later either.
+    reach: Option<HashSet<Point3D>>,
+}
+
+impl Site<'_> {
+    fn is_free(&self, p: Point3D) -> bool {
+        p.x >= self.min.x
+            && p.y >= self.min.y
+            && p.z >= self.min.z
+            && p.x <= self.max.x
+            && p.y <= self.max.y
+            && p.z <= self.max.z
+            && !self.placed.contains(&p)
+            && (self.dug.contains(&p) || self.solid.get(&p) != Some(&true))
+    }
+
+    /// Move the turtle through free cells to the nearest of `goals`. Returns
+    /// false, without moving, if none of them can be reached.
+    fn travel(&mut self, plan: &mut Planner, goals: &[Point3D]) -> bool {
+        if let Some(reach) = &self.reach
+            && !goals.iter().any(|g| reach.contains(g))
+        {
+            return false;
+        }
+        let start = plan.position;
+        let mut came_from: HashMap<Point3D, Point3D> = HashMap::from([(start, start)]);
+        let mut queue = std::collections::VecDeque::from([start]);
+        while let Some(p) = queue.pop_front() {
+            if goals.contains(&p) {
+                let mut path = vec![p];
+                while let Some(&prev) = came_from.get(path.last().unwrap())
+                    && prev != *path.last().unwrap()
+                {
+                    path.push(prev);
+                }
+                for &cell in path.iter().rev().skip(1) {
+                    plan.step(cell);
+                }
+                return true;
+            }
+            for d in NEIGHBOURS {
+                let next = p.offset(d, 1);
+                if self.is_free(next) && !came_from.contains_key(&next) {
+                    came_from.insert(next, p);
+                    queue.push_back(next);
+                }
+            }
+        }
+        self.reach = Some(came_from.into_keys().collect());
+        false
+    }
+}
+
+// Cut everything above the target height from the top down, then fill the
+// holes below it from the bottom up.
+pub fn flatten(
+    start: Point3D,
+    targets: &[Block],
+    storage: Option<Point3D>,
+    solid: &HashMap<Point3D, bool>,
+) -> Vec<String> {
+    let mut cut: Vec<Point3D> = targets
+        .iter()
+        .filter(|b| !b.is_solid())
+        .map(|b| b.position())
+        .collect();
+    cut.sort_by_key(|p| {
+        let z = if p.x % 2 == 0 { p.z } else { -p.z };
+        (-p.y, p.x, z)
+    });
+    let mut plan = Planner::new(start);
+    for &p in &cut {
+        plan.walk(p);
+    }
+    place_in_terrain(
+        &mut plan,
+        targets,
+        storage,
+        solid,
+        cut.into_iter().collect(),
+    );
+    plan.steps
+}
+
+/// Steps in a bridge from `start` to `end`, one per block along its longest
+/// axis. Worked out in i64 since the ends can be any two points.
+pub fn bridge_steps(start: Point3D, end: Point3D) -> i64 {
+    let d = |a: i32, b: i32| (b as i64 - a as i64).abs();
+    d(start.x, end.x)
+        .max(d(start.y, end.y))
+        .max(d(start.z, end.z))
+        .max(1)
+}
+
+/// A walkable deck from `start` to `end`. Diagonal runs get an extra cell at
+/// each corner so the deck stays connected edge to edge.
+pub fn bridge_targets(
+    start: Point3D,
+    end: Point3D,
+    block_type: &str,
+    solid: &HashMap<Point3D, bool>,
+) -> Vec<Block> {
+    let n = bridge_steps(start, end);
+    // Rounded to the nearest block; always between `a` and `b`, so it fits
+    let along = |a: i32, b: i32, i: i64| {
+        let d = b as i128 - a as i128;
+        let n = n as i128;
+        (a as i128 + (d * i as i128 + n / 2 * d.signum()) / n) as i32
+    };
+    let mut cells: Vec<Point3D> = Vec::new();
+    for i in 0..=n {
+        let p = Point3D::new(
+            along(start.x, end.x, i),
+            along(start.y, end.y, i),
+            along(start.z, end.z, i),
+        );
+        if let Some(&last) = cells.last()
+            && last.x != p.x
+            && last.z != p.z
+        {
+            cells.push(Point3D::new(p.x, last.y, last.z));
+        }
+        if cells.last() != Some(&p) {
+            cells.push(p);
+        }
+    }
+    cells
+        .into_iter()
+        .filter(|p| !solid.get(p).copied().unwrap_or(false))
+        .map(|p| Block::new(p, block_type.to_string()))
+        .collect()
+}
+
+// Trees sit on the bottom layer of the plot. The turtle walks a lane just
+// north of each row, faces south at every tree and lets `fell` on the turtle
+// decide whether there's a grown tree to cut, a sapling to leave or an empty
+// spot to plant. Rows are joined outside the plot so no trunk is walked into.
+fn tree_farm(
+    top_corner: Point3D,
+    bottom_corner: Point3D,
+    spacing: u32,
+    sapling: &str,
+    chest: Point3D,
+) -> Vec<String> {
+    let (min, max) = bounds(top_corner, bottom_corner);
+    let step = spacing as usize + 1;
+    let y = min.y;
+    let mut plan = Planner::new(Point3D::new(min.x, y, min.z - 1));
+    let mut eastward = true;
+    for z in (min.z..=max.z).step_by(step) {
+        let mut xs: Vec<i32> = (min.x..=max.x).step_by(step).collect();
+        if !eastward {
+            xs.reverse();
+        }
+        // Move over to this row's lane while still outside the plot
+        plan.walk(Point3D::new(plan.position.x, y, z - 1));
+        for x in xs {
+            plan.walk(Point3D::new(x, y, z - 1));
+            plan.push("facesouth");
+            plan.steps.push(format!("fell {}", sapling));
+        }
+        let edge = if eastward { max.x + 1 } else { min.x - 1 };
+        plan.walk(Point3D::new(edge, y, z - 1));
+        eastward = !eastward;
+    }
+    deposit(&mut plan, chest, sapling);
+    plan.steps
+}
+
+// Crops sit on the bottom layer of the plot and are worked from the layer
+// above, where nothing grows tall enough to be in the way.
+fn crop_farm(
+    top_corner: Point3D,
+    bottom_corner: Point3D,
+    seed: &str,
+    chest: Point3D,
+) -> Vec<String> {
+    let (min, max) = bounds(top_corner, bottom_corner);
+    let mut plan = Planner::new(Point3D::new(min.x, min.y + 1, min.z));
+    let mut forward = true;
+    for x in min.x..=max.x {
+        let zs: Vec<i32> = if forward {
+            (min.z..=max.z).collect()
+        } else {
+            (min.z..=max.z).rev().collect()
+        };
+        for z in zs {
+            plan.walk(Point3D::new(x, min.y + 1, z));
+            plan.steps.push(format!("harvestdown {}", seed));
+        }
+        forward = !forward;
+    }
+    deposit(&mut plan, chest, seed);
+    plan.steps
+}
+
+// Stand on the chest and drop everything except what the next run replants.
+fn deposit(plan: &mut Planner, chest: Point3D, keep: &str) {
+    plan.walk(Point3D::new(chest.x, chest.y + 1, chest.z));
+    plan.steps.push(format!("deposit {}", keep));
+}
+
+// How far around the turtle to look for the next stop before falling back to
+// the nearest unexplored cell anywhere in the region.
+const EXPLORE_RADIUS: i32 = 4;
+
+const NEIGHBOURS: [Point3D; 6] = [
+    Point3D { x: 1, y: 0, z: 0 },
+    Point3D { x: -1, y: 0, z: 0 },
+    Point3D { x: 0, y: 1, z: 0 },
+    Point3D { x: 0, y: -1, z: 0 },
+    Point3D { x: 0, y: 0, z: 1 },
+    Point3D { x: 0, y: 0, z: -1 },
+];
+
+// Greedy frontier exploration. At every stop the turtle runs `reportarea`,
+// which reports all six neighbours. The next stop is the nearby cell that
+// reveals the most unknown cells per block travelled. Cells the turtle digs
+// through on the way count as seen too.
+pub fn explore(a: Point3D, b: Point3D, coverage: f32, known: &HashSet<Point3D>) -> Vec<String> {
+    let (min, max) = bounds(a, b);
+    let in_region = |p: &Point3D| {
+        p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y && p.z >= min.z && p.z <= max.z
+    };
+    let total = box_cells(min, max).count();
+    let wanted = (total as f32 * coverage).ceil() as usize;
+    let mut seen: HashSet<Point3D> = box_cells(min, max).filter(|p| known.contains(p)).collect();
+    // Cells not seen yet, in box order, for when there's nothing left to see
+    // nearby. Ones seen since are dropped off the front as they come up, so
+    // every cell is passed over once in all.
+    let mut unseen: VecDeque<Point3D> = box_cells(min, max).filter(|p| !seen.contains(p)).collect();
+    let gain = |p: Point3D, seen: &HashSet<Point3D>| {
+        NEIGHBOURS
+            .iter()
+            .chain(std::iter::once(&Point3D { x: 0, y: 0, z: 0 }))
+            .map(|d| p.offset(*d, 1))
+            .filter(|n| in_region(n) && !seen.contains(n))
+            .count()
+    };
+
+    let mut plan = Planner::new(a);
+    while seen.len() < wanted {
+        let here = plan.position;
+        let mut best: Option<(f32, Point3D)> = None;
+        for dx in -EXPLORE_RADIUS..=EXPLORE_RADIUS {
+            for dy in -EXPLORE_RADIUS..=EXPLORE_RADIUS {
+                for dz in -EXPLORE_RADIUS..=EXPLORE_RADIUS {
+                    let p = Point3D::new(here.x + dx, here.y + dy, here.z + dz);
+                    if !in_region(&p) {
+                        continue;
+                    }
+                    let g = gain(p, &seen);
+                    if g == 0 {
+                        continue;
+                    }
+                    // Every stop also costs the four turns of `reportarea`
+                    let score = g as f32 / (here.manhattan_distance(&p) + 4) as f32;
+                    if best.is_none_or(|(s, _)| score > s) {
+                        best = Some((score, p));
+                    }
+                }
+            }
+        }
+        let next = match best {
+            Some((_, p)) => p,
+            None => {
+                while unseen.front().is_some_and(|p| seen.contains(p)) {
+                    unseen.pop_front();
+                }
+                match unseen.front() {
+                    Some(&p) => p,
+                    None => break,
+                }
+            }
+        };
+
+        let start = plan.steps.len();
+        plan.walk(next);
+        if plan.steps.len() > start {
+            // Cells walked into were dug out, so they're known now
+            let mut p = here;
+            for step in &plan.steps[start..] {
+                p = match step.as_str() {
+                    "up" => p.offset(NEIGHBOURS[2], 1),
+                    "down" => p.offset(NEIGHBOURS[3], 1),
+                    "east" => p.offset(NEIGHBOURS[0], 1),
+                    "west" => p.offset(NEIGHBOURS[1], 1),
+                    "south" => p.offset(NEIGHBOURS[4], 1),
+                    "north" => p.offset(NEIGHBOURS[5], 1),
+                    _ => continue,
+                };
+                seen.insert(p);
+            }
+        }
+        plan.push("reportarea");
+        seen.insert(next);
+        for d in &NEIGHBOURS {
+            let n = next.offset(*d, 1);
+            if in_region(&n) {
+                seen.insert(n);
+            }
+        }
+    }
+    plan.steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinder::split_count;
+
+    /// Runs `steps` like a turtle would on a world where `solid` holds every
+    /// solid cell. Panics on digging anything not in `diggable`, on moving into
+    /// a solid cell and on placing into one. Returns the cells placed.
+    fn run(
+        start: Point3D,
+        steps: &[String],
+        solid: &mut HashSet<Point3D>,
+        diggable: &HashSet<Point3D>,
+    ) -> HashSet<Point3D> {
+        let mut pos = start;
+        let mut facing = NEIGHBOURS[5];
+        let mut placed = HashSet::new();
+        let direction = |word: &str| match word {
+            "east" => NEIGHBOURS[0],
+            "west" => NEIGHBOURS[1],
+            "south" => NEIGHBOURS[4],
+            _ => NEIGHBOURS[5],
+        };
+        for step in steps {
+            let (word, count) = split_count(step);
+            for _ in 0..count {
+                let (dig, go, put) = match word {
+                    "north" | "east" | "south" | "west" => {
+                        facing = direction(word);
+                        (None, Some(facing), None)
+                    }
+                    w if w.starts_with("face") => {
+                        facing = direction(&w[4..]);
+                        (None, None, None)
+                    }
+                    "forward" => (None, Some(facing), None),
+                    "up" => (None, Some(NEIGHBOURS[2]), None),
+                    "down" => (None, Some(NEIGHBOURS[3]), None),
+                    "dig" => (Some(facing), None, None),
+                    "digup" => (Some(NEIGHBOURS[2]), None, None),
+                    "digdown" => (Some(NEIGHBOURS[3]), None, None),
+                    "place" => (None, None, Some(facing)),
+                    "placeup" => (None, None, Some(NEIGHBOURS[2])),
+                    "placedown" => (None, None, Some(NEIGHBOURS[3])),
+                    _ => (None, None, None),
+                };
+                if let Some(d) = dig {
+                    let p = pos.offset(d, 1);
+                    if solid.remove(&p) {
+                        assert!(diggable.contains(&p), "dug {:?}", p);
+                    }
+                }
+                if let Some(d) = go {
+                    pos = pos.offset(d, 1);
+                    assert!(!solid.contains(&pos), "moved into {:?}", pos);
+                }
+                if let Some(d) = put {
+                    let p = pos.offset(d, 1);
+                    assert!(solid.insert(p), "placed into {:?}", p);
+                    placed.insert(p);
+                }
+            }
+        }
+        placed
+    }
+
+    fn known(solid: &HashSet<Point3D>, air: &HashSet<Point3D>) -> HashMap<Point3D, bool> {
+        let mut map: HashMap<Point3D, bool> = air.iter().map(|p| (*p, false)).collect();
+        map.extend(solid.iter().map(|p| (*p, true)));
+        map
+    }
+
+    #[test]
+    fn fill_under_ground_digs_nothing_else() {
+        // A hollow under a slab of ground, on a floor
+        let hollow: HashSet<Point3D> =
+            box_cells(Point3D::new(1, 0, 1), Point3D::new(3, 2, 3)).collect();
+        let mut solid: HashSet<Point3D> = box_cells(Point3D::new(0, -1, 0), Point3D::new(4, 3, 4))
+            .filter(|p| !hollow.contains(p))
+            .collect();
+        let map = known(&solid, &hollow);
+        // Where the job starts: in the ground above the box's corner, which
+        // the trip there dug out
+        let start = Point3D::new(1, 3, 1);
+        solid.remove(&start);
+
+        let targets = fill_targets(Point3D::new(1, 2, 1), Point3D::new(3, 0, 3), "stone", &map);
+        assert_eq!(targets.len(), hollow.len());
+        let steps = fill(start, &targets, None, &map);
+        let placed = run(start, &steps, &mut solid, &HashSet::new());
+        assert_eq!(placed, hollow);
+    }
+
+    #[test]
+    fn fill_in_the_open_places_everything() {
+        let air: HashSet<Point3D> =
+            box_cells(Point3D::new(0, 0, 0), Point3D::new(3, 1, 2)).collect();
+        let mut solid: HashSet<Point3D> =
+            box_cells(Point3D::new(-1, -1, -1), Point3D::new(4, -1, 3)).collect();
+        let map = known(&solid, &air);
+        let targets = fill_targets(Point3D::new(0, 1, 0), Point3D::new(3, 0, 2), "dirt", &map);
+        let start = Point3D::new(0, 2, 0);
+        let steps = fill(start, &targets, None, &map);
+        assert_eq!(run(start, &steps, &mut solid, &HashSet::new()), air);
+    }
+
+    #[test]
+    fn flatten_leaves_ground_it_cant_reach() {
+        // Flat ground at y = 0 with a hill on it, a dip in the surface and
+        // a cave under the surface
+        let hill = Point3D::new(2, 1, 2);
+        let dip = Point3D::new(0, 0, 2);
+        let cave = Point3D::new(1, -1, 1);
+        let mut solid: HashSet<Point3D> =
+            box_cells(Point3D::new(-1, -2, -1), Point3D::new(3, 0, 3))
+                .filter(|p| *p != dip && *p != cave)
+                .chain([hill])
+                .collect();
+        let air: HashSet<Point3D> = box_cells(Point3D::new(0, 1, 0), Point3D::new(2, 2, 2))
+            .filter(|p| *p != hill)
+            .chain([dip, cave])
+            .collect();
+        let map = known(&solid, &air);
+        let targets = flatten_targets(
+            Point3D::new(0, 2, 0),
+            Point3D::new(2, -1, 2),
+            0,
+            "dirt",
+            &map,
+        );
+        let start = Point3D::new(0, 2, 0);
+        let steps = flatten(start, &targets, None, &map);
+        let placed = run(start, &steps, &mut solid, &HashSet::from([hill]));
+        assert!(!solid.contains(&hill));
+        // The dip is open to the air and gets filled. The cave would mean
+        // digging through the ground over it, so it's left.
+        assert_eq!(placed, HashSet::from([dip]));
+    }
+
+    #[test]
+    fn long_bridges_stay_connected() {
+        let empty = HashMap::new();
+        for (start, end) in [
+            // Far enough that `dx * i` overflows an i32
+            (
+                Point3D::new(-30_000, 64, 5),
+                Point3D::new(30_000, 64, 30_005),
+            ),
+            // Climbing faster than it goes across
+            (Point3D::new(0, 0, 0), Point3D::new(2, 10, -1)),
+        ] {
+            let cells: Vec<Point3D> = bridge_targets(start, end, "dirt", &empty)
+                .iter()
+                .map(|b| b.position())
+                .collect();
+            assert_eq!(cells.first(), Some(&start));
+            assert_eq!(cells.last(), Some(&end));
+            // Edge to edge across, and at most a block up or down per step
+            for pair in cells.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert!(a.x.abs_diff(b.x) + a.z.abs_diff(b.z) <= 1, "{:?}", pair);
+                assert!(a.y.abs_diff(b.y) <= 1, "{:?}", pair);
+                assert_ne!(a, b);
+            }
+            let corners = start.x.abs_diff(end.x).min(start.z.abs_diff(end.z)) as usize;
+            assert!(cells.len() <= bridge_steps(start, end) as usize + 1 + corners);
+        }
+    }
+
+    #[test]
+    fn explore_sees_a_box_too_wide_to_see_from_one_spot() {
+        let (a, b) = (Point3D::new(0, 0, 0), Point3D::new(24, 1, 24));
+        let steps = explore(a, b, 1.0, &HashSet::new());
+        // Every stop reports itself and its neighbours
+        let mut pos = a;
+        let mut seen = HashSet::from([a]);
+        for step in &steps {
+            let (word, count) = split_count(step);
+            let d = match word {
+                "east" => NEIGHBOURS[0],
+                "west" => NEIGHBOURS[1],
+                "up" => NEIGHBOURS[2],
+                "down" => NEIGHBOURS[3],
+                "south" => NEIGHBOURS[4],
+                "north" => NEIGHBOURS[5],
+                "reportarea" => {
+                    seen.extend(NEIGHBOURS.iter().map(|d| pos.offset(*d, 1)));
+                    continue;
+                }
+                _ => continue,
+            };
+            for _ in 0..count {
+                pos = pos.offset(d, 1);
+                seen.insert(pos);
+            }
+        }
+        assert!(box_cells(a, b).all(|p| seen.contains(&p)));
+    }
+}
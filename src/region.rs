@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Message;
+use crate::pathfinder::Point3D;
+use crate::plan;
+use crate::state::AppState;
+use crate::turtle::{Block, WorldId};
+
+/// Parses `x1,y1,z1,x2,y2,z2` into the box's smallest and largest corner.
+pub fn parse_region(region: &str) -> Result<(Point3D, Point3D), String> {
+    let n: Vec<i32> = region
+        .split(',')
+        .map(|s| s.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "region must be six integers".to_string())?;
+    let [x1, y1, z1, x2, y2, z2] = n[..] else {
+        return Err("region must be six integers".to_string());
+    };
+    Ok(plan::bounds(
+        Point3D::new(x1, y1, z1),
+        Point3D::new(x2, y2, z2),
+    ))
+}
+
+/// The world a query names as `server/dimension`, or the default one.
+fn parse_world(world: Option<&str>) -> Result<WorldId, String> {
+    world.map_or(Ok(WorldId::default()), str::parse)
+}
+
+fn bad_request(text: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(Message { text })).into_response()
+}
+
+/// Cells along each axis of the box, inclusive.
+fn size_of(min: Point3D, max: Point3D) -> [u64; 3] {
+    [
+        (max.x as i64 - min.x as i64 + 1) as u64,
+        (max.y as i64 - min.y as i64 + 1) as u64,
+        (max.z as i64 - min.z as i64 + 1) as u64,
+    ]
+}
+
+/// Cells in the box, or None if there are too many to count.
+fn cells_in(min: Point3D, max: Point3D) -> Option<u64> {
+    let [dx, dy, dz] = size_of(min, max);
+    dx.checked_mul(dy)?.checked_mul(dz)
+}
+
+fn too_big(what: &str, max_cells: u64) -> Response {
+    bad_request(format!(
+        "Region has more {} than the {} allowed",
+        what, max_cells
+    ))
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A list of known blocks.
+    #[default]
+    Json,
+    /// Every cell of the box as an index into a palette, see `encode_palette`.
+    Palette,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegionQuery {
+    world: Option<String>,
+    /// `x1,y1,z1,x2,y2,z2`
+    region: String,
+    #[serde(default)]
+    format: Format,
+}
+
+impl RegionQuery {
+    fn parse(&self) -> Result<(WorldId, Point3D, Point3D), String> {
+        let world = parse_world(self.world.as_deref())?;
+        let (min, max) = parse_region(&self.region)?;
+        Ok((world, min, max))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BlockInfo<'a> {
+    position: Point3D,
+    block_type: &'a str,
+}
+
+/// `GET /world/blocks`: known blocks inside a box.
+pub async fn get_blocks(
+    State(st): State<AppState>,
+    Query(query): Query<RegionQuery>,
+) -> impl IntoResponse {
+    let (world_id, min, max) = match query.parse() {
+        Ok(parsed) => parsed,
+        Err(text) => return bad_request(text),
+    };
+    let max_cells = st.config().await.query.max_cells;
+    if cells_in(min, max).is_none_or(|cells| cells > max_cells) {
+        return too_big("cells", max_cells);
+    }
+
+    let worlds = st.worlds.read().await;
+    let world = worlds.get(&world_id);
+    match query.format {
+        Format::Json => {
+            let blocks: Vec<BlockInfo> = world
+                .blocks_in(min, max)
+                .map(|b| BlockInfo {
+                    position: b.position(),
+                    block_type: b.name(),
+                })
+                .collect();
+            Json(blocks).into_response()
+        }
+        Format::Palette => match encode_palette(min, max, world.blocks_in(min, max)) {
+            Ok(body) => ([(CONTENT_TYPE, "application/octet-stream")], body).into_response(),
+            Err(text) => bad_request(text),
+        },
+    }
+}
+
+/// Packs every cell of the box, little-endian:
+///
+/// - the smallest corner as three `i32`s and the size as three `u32`s
+/// - the palette: a `u32` count, then each block id as a `u16` byte length
+///   and UTF-8
+/// - one `u16` per cell, 0 for unknown or else the palette index plus 1,
+///   with x changing fastest, then z, then y
+fn encode_palette<'a>(
+    min: Point3D,
+    max: Point3D,
+    blocks: impl Iterator<Item = &'a Block>,
+) -> Result<Vec<u8>, String> {
+    let [dx, dy, dz] = size_of(min, max).map(|n| n as usize);
+    let mut palette: Vec<&str> = Vec::new();
+    let mut lookup: HashMap<&str, u16> = HashMap::new();
+    let mut cells = vec![0u16; dx * dy * dz];
+    for block in blocks {
+        let name = block.name();
+        let index = match lookup.get(name) {
+            Some(&i) => i,
+            None => {
+                // Lengths and indexes are written as u16s
+                if u16::try_from(name.len()).is_err() {
+                    return Err(format!("Block id at {:?} is too long", block.position()));
+                }
+                palette.push(name);
+                let Ok(i) = u16::try_from(palette.len()) else {
+                    return Err("Region has too many block types".to_string());
+                };
+                lookup.insert(name, i);
+                i
+            }
+        };
+        let p = block.position();
+        let (x, y, z) = (
+            (p.x - min.x) as usize,
+            (p.y - min.y) as usize,
+            (p.z - min.z) as usize,
+        );
+        cells[(y * dz + z) * dx + x] = index;
+    }
+
+    let mut out = Vec::with_capacity(28 + palette.len() * 24 + cells.len() * 2);
+    for n in [min.x, min.y, min.z] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    for n in [dx, dy, dz] {
+        out.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+    out.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    for name in palette {
+        // Checked above
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+    }
+    for cell in cells {
+        out.extend_from_slice(&cell.to_le_bytes());
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlockQuery {
+    world: Option<String>,
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+/// `GET /world/block`: one cached block.
+pub async fn get_block(
+    State(st): State<AppState>,
+    Query(query): Query<BlockQuery>,
+) -> impl IntoResponse {
+    let world_id = match parse_world(query.world.as_deref()) {
+        Ok(world_id) => world_id,
+        Err(text) => return bad_request(text),
+    };
+    let worlds = st.worlds.read().await;
+    let position = Point3D::new(query.x, query.y, query.z);
+    match worlds.get(&world_id).get_block(position) {
+        Some(block) => Json(BlockInfo {
+            position,
+            block_type: block.name(),
+        })
+        .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(Message {
+                text: "Block isn't known".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BlockCounts<'a> {
+    /// Cells of the box with nothing cached.
+    unknown: u64,
+    blocks: BTreeMap<&'a str, u64>,
+}
+
+/// `GET /world/counts`: how many of each block type are in a box.
+pub async fn count_blocks(
+    State(st): State<AppState>,
+    Query(query): Query<RegionQuery>,
+) -> impl IntoResponse {
+    let (world_id, min, max) = match query.parse() {
+        Ok(parsed) => parsed,
+        Err(text) => return bad_request(text),
+    };
+    // Only known blocks are visited, so big boxes are fine as long as the
+    // count fits
+    let Some(cells) = cells_in(min, max) else {
+        return bad_request("Region is too big to count".to_string());
+    };
+    let worlds = st.worlds.read().await;
+    let mut blocks = BTreeMap::new();
+    let mut known = 0;
+    for block in worlds.get(&world_id).blocks_in(min, max) {
+        *blocks.entry(block.name()).or_insert(0) += 1;
+        known += 1;
+    }
+    Json(BlockCounts {
+        unknown: cells - known,
+        blocks,
+    })
+    .into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct Heightmap {
+    /// The column at `heights[0]`.
+    x: i32,
+    z: i32,
+    width: u32,
+    depth: u32,
+    /// Y of the topmost known solid block in each column of the box, or null
+    /// if none is known, with x changing fastest.
+    heights: Vec<Option<i32>>,
+}
+
+/// `GET /world/heightmap`: the topmost known solid block per column of a
+/// box, looking only between its two Y levels.
+pub async fn heightmap(
+    State(st): State<AppState>,
+    Query(query): Query<RegionQuery>,
+) -> impl IntoResponse {
+    let (world_id, min, max) = match query.parse() {
+        Ok(parsed) => parsed,
+        Err(text) => return bad_request(text),
+    };
+    let [dx, _, dz] = size_of(min, max);
+    let max_cells = st.config().await.query.max_cells;
+    if dx.checked_mul(dz).is_none_or(|columns| columns > max_cells) {
+        return too_big("columns", max_cells);
+    }
+    let (dx, dz) = (dx as usize, dz as usize);
+    let mut heights: Vec<Option<i32>> = vec![None; dx * dz];
+    let worlds = st.worlds.read().await;
+    for block in worlds.get(&world_id).blocks_in(min, max) {
+        if !block.is_solid() {
+            continue;
+        }
+        let p = block.position();
+        let top = &mut heights[(p.z - min.z) as usize * dx + (p.x - min.x) as usize];
+        if top.is_none_or(|y| p.y > y) {
+            *top = Some(p.y);
+        }
+    }
+    Json(Heightmap {
+        x: min.x,
+        z: min.z,
+        width: dx as u32,
+        depth: dz as u32,
+        heights,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Decoded {
+        min: [i32; 3],
+        size: [u32; 3],
+        palette: Vec<String>,
+        cells: Vec<u16>,
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        let mut at = 0;
+        let mut take = |n: usize| {
+            let chunk = &bytes[at..at + n];
+            at += n;
+            chunk
+        };
+        let min = [0; 3].map(|_| i32::from_le_bytes(take(4).try_into().unwrap()));
+        let size = [0; 3].map(|_| u32::from_le_bytes(take(4).try_into().unwrap()));
+        let count = u32::from_le_bytes(take(4).try_into().unwrap());
+        let palette = (0..count)
+            .map(|_| {
+                let len = u16::from_le_bytes(take(2).try_into().unwrap());
+                String::from_utf8(take(len as usize).to_vec()).unwrap()
+            })
+            .collect();
+        let cells = (0..size.iter().product::<u32>())
+            .map(|_| u16::from_le_bytes(take(2).try_into().unwrap()))
+            .collect();
+        assert_eq!(at, bytes.len(), "trailing bytes");
+        Decoded {
+            min,
+            size,
+            palette,
+            cells,
+        }
+    }
+
+    fn block(x: i32, y: i32, z: i32, block_type: &str) -> Block {
+        Block::new(Point3D::new(x, y, z), block_type.to_string())
+    }
+
+    #[test]
+    fn unknown_cells_are_zero() {
+        let min = Point3D::new(-1, 60, 5);
+        let max = Point3D::new(0, 61, 5);
+        let decoded = decode(&encode_palette(min, max, std::iter::empty()).unwrap());
+        assert_eq!(decoded.min, [-1, 60, 5]);
+        assert_eq!(decoded.size, [2, 2, 1]);
+        assert!(decoded.palette.is_empty());
+        assert_eq!(decoded.cells, vec![0; 4]);
+    }
+
+    #[test]
+    fn single_cell() {
+        let p = Point3D::new(3, 4, 5);
+        let blocks = [block(3, 4, 5, "minecraft:stone")];
+        let decoded = decode(&encode_palette(p, p, blocks.iter()).unwrap());
+        assert_eq!(decoded.size, [1, 1, 1]);
+        assert_eq!(decoded.palette, ["minecraft:stone"]);
+        assert_eq!(decoded.cells, [1]);
+    }
+
+    #[test]
+    fn types_share_palette_entries() {
+        let blocks = [
+            block(0, 0, 0, "minecraft:stone"),
+            block(1, 0, 0, "minecraft:dirt"),
+            block(0, 0, 1, "minecraft:stone"),
+            block(1, 0, 1, "a"),
+        ];
+        let max = Point3D::new(1, 0, 1);
+        let decoded = decode(&encode_palette(Point3D::new(0, 0, 0), max, blocks.iter()).unwrap());
+        // Air is spelled out rather than sent as the cache's shorthand
+        assert_eq!(
+            decoded.palette,
+            ["minecraft:stone", "minecraft:dirt", "minecraft:air"]
+        );
+        assert_eq!(decoded.cells, [1, 2, 1, 3]);
+    }
+
+    #[test]
+    fn x_changes_fastest_then_z_then_y() {
+        let min = Point3D::new(10, 20, 30);
+        let max = Point3D::new(11, 21, 32);
+        let blocks = [
+            block(11, 20, 30, "x"),
+            block(10, 20, 31, "z"),
+            block(10, 21, 30, "y"),
+            block(11, 21, 32, "last"),
+        ];
+        let decoded = decode(&encode_palette(min, max, blocks.iter()).unwrap());
+        assert_eq!(decoded.size, [2, 2, 3]);
+        let mut expected = vec![0; 12];
+        expected[1] = 1;
+        expected[2] = 2;
+        expected[6] = 3;
+        expected[11] = 4;
+        assert_eq!(decoded.cells, expected);
+    }
+
+    #[test]
+    fn too_many_block_types() {
+        let max = Point3D::new(255, 0, 255);
+        let blocks: Vec<Block> = (0..256 * 256)
+            .map(|i| block(i % 256, 0, i / 256, &format!("type{}", i)))
+            .collect();
+        assert!(encode_palette(Point3D::new(0, 0, 0), max, blocks.iter().take(65535)).is_ok());
+        assert!(encode_palette(Point3D::new(0, 0, 0), max, blocks.iter()).is_err());
+    }
+
+    #[test]
+    fn block_id_too_long() {
+        let p = Point3D::new(0, 0, 0);
+        let blocks = [block(0, 0, 0, &"x".repeat(65535))];
+        assert!(encode_palette(p, p, blocks.iter()).is_ok());
+        let blocks = [block(0, 0, 0, &"x".repeat(65536))];
+        assert!(encode_palette(p, p, blocks.iter()).is_err());
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::Message;
+use crate::job::JobStatus;
+use crate::pathfinder::Point3D;
+use crate::region;
+use crate::state::AppState;
+use crate::turtle::{Block, WorldId};
+
+/// Largest `scale` a request can ask for.
+pub const MAX_SCALE: u32 = 32;
+
+/// The `[render]` section of `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Render {
+    /// Pixels per block side, unless a request asks for another.
+    pub scale: u32,
+    /// Bigger images are refused.
+    pub max_pixels: u64,
+    /// Block id to `#rrggbb`, on top of the built-in colors.
+    pub colors: BTreeMap<String, String>,
+}
+
+impl Default for Render {
+    fn default() -> Self {
+        Render {
+            scale: 4,
+            max_pixels: 16_000_000,
+            colors: BTreeMap::new(),
+        }
+    }
+}
+
+// Blocks turtles run into most; anything else gets a color made from its id
+const BUILT_IN_COLORS: &[(&str, [u8; 3])] = &[
+    ("minecraft:air", [196, 220, 240]),
+    ("minecraft:stone", [125, 125, 125]),
+    ("minecraft:cobblestone", [110, 110, 110]),
+    ("minecraft:andesite", [136, 136, 136]),
+    ("minecraft:diorite", [188, 188, 188]),
+    ("minecraft:granite", [149, 103, 85]),
+    ("minecraft:tuff", [108, 109, 102]),
+    ("minecraft:deepslate", [80, 80, 85]),
+    ("minecraft:cobbled_deepslate", [77, 77, 80]),
+    ("minecraft:bedrock", [40, 40, 40]),
+    ("minecraft:dirt", [134, 96, 67]),
+    ("minecraft:grass_block", [95, 159, 53]),
+    ("minecraft:sand", [219, 207, 163]),
+    ("minecraft:gravel", [136, 126, 125]),
+    ("minecraft:clay", [160, 166, 179]),
+    ("minecraft:snow", [249, 254, 254]),
+    ("minecraft:ice", [145, 183, 253]),
+    ("minecraft:water", [63, 118, 228]),
+    ("minecraft:lava", [207, 92, 20]),
+    ("minecraft:oak_log", [109, 85, 50]),
+    ("minecraft:oak_leaves", [60, 120, 40]),
+    ("minecraft:coal_ore", [46, 46, 46]),
+    ("minecraft:iron_ore", [175, 140, 119]),
+    ("minecraft:copper_ore", [124, 125, 120]),
+    ("minecraft:gold_ore", [252, 238, 75]),
+    ("minecraft:redstone_ore", [170, 20, 20]),
+    ("minecraft:lapis_ore", [30, 70, 160]),
+    ("minecraft:diamond_ore", [93, 236, 245]),
+    ("minecraft:emerald_ore", [23, 221, 98]),
+    ("minecraft:obsidian", [20, 18, 30]),
+    ("minecraft:netherrack", [111, 54, 53]),
+    ("minecraft:soul_sand", [81, 62, 50]),
+    ("minecraft:basalt", [80, 81, 86]),
+    ("minecraft:end_stone", [219, 222, 158]),
+];
+
+const TURTLE_COLOR: [u8; 4] = [230, 30, 30, 255];
+const OUTLINE_COLOR: [u8; 4] = [0, 0, 0, 255];
+
+/// Parses `#rrggbb`.
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Block id to color, from the config and the built-in table.
+struct Colors<'a> {
+    colors: HashMap<&'a str, [u8; 3]>,
+}
+
+impl<'a> Colors<'a> {
+    fn new(render: &'a Render) -> Self {
+        let mut colors: HashMap<&str, [u8; 3]> = BUILT_IN_COLORS.iter().copied().collect();
+        for (block, color) in &render.colors {
+            // Checked when the config was loaded
+            if let Some(color) = parse_color(color) {
+                colors.insert(block, color);
+            }
+        }
+        Colors { colors }
+    }
+
+    fn get(&self, block: &Block) -> [u8; 4] {
+        let [r, g, b] = self
+            .colors
+            .get(block.name())
+            .copied()
+            .unwrap_or_else(|| hashed_color(block.name()));
+        [r, g, b, 255]
+    }
+
+    fn air(&self) -> [u8; 4] {
+        let [r, g, b] = self.colors["minecraft:air"];
+        [r, g, b, 255]
+    }
+}
+
+// Keeps unlisted blocks apart from each other and the same across renders
+fn hashed_color(name: &str) -> [u8; 3] {
+    let hash = name.bytes().fold(0x811c9dc5u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x01000193)
+    });
+    let [r, g, b, _] = hash.to_le_bytes();
+    [64 + r / 2, 64 + g / 2, 64 + b / 2]
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Color of the topmost known solid block in each column.
+    #[default]
+    Top,
+    /// Height of the topmost known solid block in each column, in gray.
+    Height,
+    /// Every block at one Y level.
+    Slice,
+    /// Every block in a vertical plane at one X or Z.
+    Section,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderQuery {
+    world: Option<String>,
+    /// `x1,y1,z1,x2,y2,z2`
+    region: String,
+    #[serde(default)]
+    mode: Mode,
+    /// The level a slice is taken at.
+    y: Option<i32>,
+    /// The plane a section is taken at; one of these.
+    x: Option<i32>,
+    z: Option<i32>,
+    scale: Option<u32>,
+    /// Draw turtles and job boundaries over the map.
+    #[serde(default = "yes")]
+    overlay: bool,
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn bad_request(text: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(Message { text })).into_response()
+}
+
+/// How world positions land on the image: which axis runs across, which
+/// runs down, and which is flattened away.
+#[derive(Debug, Clone, Copy)]
+enum View {
+    /// Looking down, x across and z down.
+    TopDown { min: Point3D, max: Point3D },
+    /// Looking along x, z across and y up.
+    AlongX { x: i32, min: Point3D, max: Point3D },
+    /// Looking along z, x across and y up.
+    AlongZ { z: i32, min: Point3D, max: Point3D },
+}
+
+impl View {
+    /// Width and height in cells, or None if either doesn't fit in a u32.
+    fn size(&self) -> Option<(u32, u32)> {
+        let (min, max) = self.bounds();
+        let span = |a: i32, b: i32| u32::try_from(b as i64 - a as i64 + 1).ok();
+        Some(match self {
+            View::TopDown { .. } => (span(min.x, max.x)?, span(min.z, max.z)?),
+            View::AlongX { .. } => (span(min.z, max.z)?, span(min.y, max.y)?),
+            View::AlongZ { .. } => (span(min.x, max.x)?, span(min.y, max.y)?),
+        })
+    }
+
+    /// The box of blocks shown.
+    fn bounds(&self) -> (Point3D, Point3D) {
+        match *self {
+            View::TopDown { min, max } => (min, max),
+            View::AlongX { x, min, max } => {
+                (Point3D::new(x, min.y, min.z), Point3D::new(x, max.y, max.z))
+            }
+            View::AlongZ { z, min, max } => {
+                (Point3D::new(min.x, min.y, z), Point3D::new(max.x, max.y, z))
+            }
+        }
+    }
+
+    /// The cell `p` lands on, ignoring the flattened axis.
+    fn project(&self, p: Point3D) -> (i64, i64) {
+        let (min, max) = self.bounds();
+        match self {
+            View::TopDown { .. } => (p.x as i64 - min.x as i64, p.z as i64 - min.z as i64),
+            View::AlongX { .. } => (p.z as i64 - min.z as i64, max.y as i64 - p.y as i64),
+            View::AlongZ { .. } => (p.x as i64 - min.x as i64, max.y as i64 - p.y as i64),
+        }
+    }
+
+    /// Whether any of the box from `lo` to `hi` is in the shown box.
+    fn shows(&self, lo: Point3D, hi: Point3D) -> bool {
+        let (min, max) = self.bounds();
+        lo.x <= max.x
+            && hi.x >= min.x
+            && lo.y <= max.y
+            && hi.y >= min.y
+            && lo.z <= max.z
+            && hi.z >= min.z
+    }
+}
+
+/// Everything needed to draw the image once the locks are released.
+struct Picture {
+    view: View,
+    width: u32,
+    height: u32,
+    cells: Vec<[u8; 4]>,
+    turtles: Vec<Point3D>,
+    jobs: Vec<([Point3D; 2], [u8; 4])>,
+}
+
+/// `GET /world/render`: a PNG map of a box of the block cache.
+pub async fn render_map(
+    State(st): State<AppState>,
+    Query(query): Query<RenderQuery>,
+) -> impl IntoResponse {
+    let world_id = match query
+        .world
+        .as_deref()
+        .map_or(Ok(WorldId::default()), str::parse)
+    {
+        Ok(world_id) => world_id,
+        Err(text) => return bad_request(text),
+    };
+    let (min, max) = match region::parse_region(&query.region) {
+        Ok(corners) => corners,
+        Err(text) => return bad_request(text),
+    };
+    let view = match (query.mode, query.y, query.x, query.z) {
+        (Mode::Top | Mode::Height, ..) => View::TopDown { min, max },
+        (Mode::Slice, Some(y), ..) => View::TopDown {
+            min: Point3D::new(min.x, y, min.z),
+            max: Point3D::new(max.x, y, max.z),
+        },
+        (Mode::Slice, None, ..) => return bad_request("A slice needs y".to_string()),
+        (Mode::Section, _, Some(x), None) => View::AlongX { x, min, max },
+        (Mode::Section, _, None, Some(z)) => View::AlongZ { z, min, max },
+        (Mode::Section, ..) => return bad_request("A section needs one of x or z".to_string()),
+    };
+
+    let config = st.config().await;
+    let scale = query.scale.unwrap_or(config.render.scale);
+    if scale == 0 || scale > MAX_SCALE {
+        return bad_request(format!("scale must be from 1 to {}", MAX_SCALE));
+    }
+    let Some((width, height)) = view.size() else {
+        return bad_request("Region is too big to draw".to_string());
+    };
+    let cells = width as u64 * height as u64;
+    if cells > config.query.max_cells {
+        return bad_request(format!(
+            "Image has {} cells, more than the {} allowed",
+            cells, config.query.max_cells
+        ));
+    }
+    let pixels = cells.saturating_mul(scale as u64 * scale as u64);
+    if pixels > config.render.max_pixels {
+        return bad_request(format!(
+            "Image has {} pixels, more than the {} allowed; try a smaller scale",
+            pixels, config.render.max_pixels
+        ));
+    }
+
+    let picture = {
+        let worlds = st.worlds.read().await;
+        let colors = Colors::new(&config.render);
+        let blocks = worlds
+            .get(&world_id)
+            .blocks_in(view.bounds().0, view.bounds().1);
+        let cells = match query.mode {
+            Mode::Top => top_colors(view, (width, height), blocks, colors.air(), |b| {
+                colors.get(b)
+            }),
+            Mode::Height => top_colors(view, (width, height), blocks, colors.air(), |b| {
+                height_gray(b.position().y, min.y, max.y)
+            }),
+            Mode::Slice | Mode::Section => {
+                let mut cells = vec![[0; 4]; width as usize * height as usize];
+                for block in blocks {
+                    let (u, v) = view.project(block.position());
+                    cells[v as usize * width as usize + u as usize] = colors.get(block);
+                }
+                cells
+            }
+        };
+        let (mut turtles, mut jobs) = (Vec::new(), Vec::new());
+        if query.overlay {
+            let all_jobs = st.jobs.read().await;
+            jobs = all_jobs
+                .iter()
+                .filter(|j| j.world == world_id)
+                .filter_map(|j| Some((j.region()?, job_color(j.status)?)))
+                .filter(|([lo, hi], _)| view.shows(*lo, *hi))
+                .collect();
+            let all_turtles = st.turtles.read().await;
+            turtles = all_turtles
+                .iter()
+                .filter(|t| *t.world() == world_id && view.shows(t.position(), t.position()))
+                .map(|t| t.position())
+                .collect();
+        }
+        Picture {
+            view,
+            width,
+            height,
+            cells,
+            turtles,
+            jobs,
+        }
+    };
+
+    // Scaling up and compressing can take a while for big maps
+    match tokio::task::spawn_blocking(move || draw(&picture, scale)).await {
+        Ok(Ok(png)) => ([(CONTENT_TYPE, "image/png")], png).into_response(),
+        Ok(Err(text)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(Message { text })).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(Message {
+                text: e.to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Each column's topmost known solid block, colored by `color`. Columns
+/// with only air known are drawn as air, and unknown ones are left clear.
+fn top_colors<'a>(
+    view: View,
+    (width, height): (u32, u32),
+    blocks: impl Iterator<Item = &'a Block>,
+    air: [u8; 4],
+    color: impl Fn(&Block) -> [u8; 4],
+) -> Vec<[u8; 4]> {
+    let mut tops: Vec<Option<&Block>> = vec![None; width as usize * height as usize];
+    for block in blocks {
+        let (u, v) = view.project(block.position());
+        let top = &mut tops[v as usize * width as usize + u as usize];
+        let higher = match top {
+            None => true,
+            Some(t) if !t.is_solid() => block.is_solid() || block.position().y > t.position().y,
+            Some(t) => block.is_solid() && block.position().y > t.position().y,
+        };
+        if higher {
+            *top = Some(block);
+        }
+    }
+    tops.into_iter()
+        .map(|top| match top {
+            None => [0; 4],
+            Some(b) if !b.is_solid() => air,
+            Some(b) => color(b),
+        })
+        .collect()
+}
+
+/// Higher is lighter, from black at `min_y` to white at `max_y`.
+fn height_gray(y: i32, min_y: i32, max_y: i32) -> [u8; 4] {
+    let range = (max_y as i64 - min_y as i64).max(1);
+    let gray = ((y as i64 - min_y as i64) * 255 / range) as u8;
+    [gray, gray, gray, 255]
+}
+
+/// Outline color for jobs still to be done; finished ones aren't drawn.
+fn job_color(status: JobStatus) -> Option<[u8; 4]> {
+    match status {
+        JobStatus::Pending => Some([250, 220, 40, 255]),
+        JobStatus::InProgress => Some([40, 220, 80, 255]),
+        JobStatus::Paused => Some([250, 140, 30, 255]),
+        JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled => None,
+    }
+}
+
+/// An RGBA image `scale` pixels per cell.
+struct Canvas {
+    width: u32,
+    height: u32,
+    scale: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, scale: u32) -> Self {
+        let (width, height) = (width * scale, height * scale);
+        Canvas {
+            width,
+            height,
+            scale,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    fn put(&mut self, x: i64, y: i64, color: [u8; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+
+    /// Fill the pixels from `(x1, y1)` up to but not including `(x2, y2)`.
+    fn fill(&mut self, x1: i64, y1: i64, x2: i64, y2: i64, color: [u8; 4]) {
+        for y in y1.max(0)..y2.min(self.height as i64) {
+            for x in x1.max(0)..x2.min(self.width as i64) {
+                self.put(x, y, color);
+            }
+        }
+    }
+
+    fn fill_cell(&mut self, u: i64, v: i64, color: [u8; 4]) {
+        let s = self.scale as i64;
+        self.fill(u * s, v * s, (u + 1) * s, (v + 1) * s, color);
+    }
+
+    /// A one pixel border just inside the cells from `a` to `b`.
+    fn outline(&mut self, a: (i64, i64), b: (i64, i64), color: [u8; 4]) {
+        let s = self.scale as i64;
+        let (x1, y1) = (a.0.min(b.0) * s, a.1.min(b.1) * s);
+        let (x2, y2) = ((a.0.max(b.0) + 1) * s, (a.1.max(b.1) + 1) * s);
+        self.fill(x1, y1, x2, y1 + 1, color);
+        self.fill(x1, y2 - 1, x2, y2, color);
+        self.fill(x1, y1, x1 + 1, y2, color);
+        self.fill(x2 - 1, y1, x2, y2, color);
+    }
+
+    /// A filled square on a cell with a dark edge, at least 3 pixels across
+    /// so it shows at any scale.
+    fn marker(&mut self, u: i64, v: i64, color: [u8; 4]) {
+        let s = self.scale as i64;
+        let size = s.max(3);
+        let (x, y) = (u * s + s / 2 - size / 2, v * s + s / 2 - size / 2);
+        self.fill(x, y, x + size, y + size, OUTLINE_COLOR);
+        self.fill(x + 1, y + 1, x + size - 1, y + size - 1, color);
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(|e| e.to_string())?;
+        writer.finish().map_err(|e| e.to_string())?;
+        Ok(png)
+    }
+}
+
+fn draw(picture: &Picture, scale: u32) -> Result<Vec<u8>, String> {
+    let (view, width, height) = (picture.view, picture.width, picture.height);
+    let mut canvas = Canvas::new(width, height, scale);
+    for (i, &color) in picture.cells.iter().enumerate() {
+        if color[3] != 0 {
+            canvas.fill_cell(i as i64 % width as i64, i as i64 / width as i64, color);
+        }
+    }
+    for ([lo, hi], color) in &picture.jobs {
+        canvas.outline(view.project(*lo), view.project(*hi), *color);
+    }
+    for &position in &picture.turtles {
+        let (u, v) = view.project(position);
+        canvas.marker(u, v, TURTLE_COLOR);
+    }
+    canvas.encode()
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::job::{JobStatus, Jobs};
+use crate::turtle::Turtles;
+
+// Fuel a turtle should still have left when it finishes a job.
+const FUEL_RESERVE: u32 = 100;
+
+/// Hand pending jobs to idle turtles that have enough fuel to finish them and
+/// carry whatever the job replants. Among the turtles that can, the closest
+/// one to the job wins. Only turtles in the job's world are considered, and
+/// turtles silent for longer than `offline_after` are left out. Turtles that
+/// haven't said how much fuel they have are trusted to have enough.
+pub fn assign_jobs(jobs: &mut Jobs, turtles: &Turtles, offline_after: Duration) {
+    let mut busy: Vec<u32> = turtles
+        .iter()
+        .map(|t| t.id())
+        .filter(|id| jobs.is_busy(*id))
+        .collect();
+
+    for job in jobs.iter_mut() {
+        if job.status != JobStatus::Pending || job.assigned_to.is_some() || !job.is_ready() {
+            continue;
+        }
+        let Some(goal) = job.path_goal() else {
+            continue;
+        };
+        // Each inventory trip is a round trip to wherever the job unloads
+        let trip_fuel = job
+            .unload_point()
+            .map(|p| goal.manhattan_distance(&p).saturating_mul(2))
+            .unwrap_or(0);
+        // Saturating, so far-off jobs just need more fuel than any turtle has
+        let needed = job
+            .estimate
+            .fuel
+            .saturating_add(job.estimate.inventory_trips.saturating_mul(trip_fuel))
+            .saturating_add(FUEL_RESERVE);
+
+        let best = turtles
+            .iter()
+            .filter(|t| !busy.contains(&t.id()) && t.hold().is_none())
+            .filter(|t| t.last_heartbeat().elapsed() <= offline_after)
+            .filter(|t| *t.world() == job.world)
+            .filter(|t| job.required_items().iter().all(|item| t.has_item(item)))
+            .map(|t| (t, t.position().manhattan_distance(&goal)))
+            .filter(|(t, travel)| {
+                t.fuel()
+                    .is_none_or(|f| f.covers(needed.saturating_add(*travel)))
+            })
+            .min_by_key(|(_, travel)| *travel);
+
+        // Otherwise the job stays pending until a turtle with enough fuel is free
+        if let Some((turtle, _)) = best {
+            info!("Assigned job {:?} to turtle {}", job.id, turtle.id());
+            job.assigned_to = Some(turtle.id());
+            busy.push(turtle.id());
+        }
+    }
+    jobs.publish_changes();
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use crate::pathfinder::Point3D;
+use crate::turtle::{Block, World};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+
+/// A structure to build, relative to the job's origin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "source")]
+pub enum Structure {
+    /// Blocks listed inline in the job request.
+    Blocks { blocks: Vec<StructureBlock> },
+    /// A Sponge `.schem` file (version 2 or 3) on the server, relative to
+    /// `storage.schematics`.
+    Schematic {
+        path: String,
+        /// Filled in by `load`.
+        #[serde(skip)]
+        blocks: Option<Vec<StructureBlock>>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructureBlock {
+    pub offset: Point3D,
+    pub block_type: String,
+}
+
+impl Structure {
+    /// Read the schematic file, if the structure comes from one. This blocks,
+    /// so it shouldn't run on the async runtime.
+    pub fn load(&mut self, dir: &str) -> Result<(), String> {
+        if let Structure::Schematic { path, blocks } = self
+            && blocks.is_none()
+        {
+            *blocks = Some(load_schematic(&schematic_path(dir, path)?, path)?);
+        }
+        Ok(())
+    }
+
+    /// How many blocks the structure has, or None if it isn't loaded.
+    pub fn block_count(&self) -> Option<usize> {
+        match self {
+            Structure::Blocks { blocks }
+            | Structure::Schematic {
+                blocks: Some(blocks),
+                ..
+            } => Some(blocks.len()),
+            Structure::Schematic { blocks: None, .. } => None,
+        }
+    }
+
+    /// Absolute positions and types of every non-air block in the structure.
+    pub fn resolve(&self, origin: Point3D) -> Result<Vec<Block>, String> {
+        let relative = match self {
+            Structure::Blocks { blocks } => blocks.clone(),
+            Structure::Schematic {
+                blocks: Some(blocks),
+                ..
+            } => blocks.clone(),
+            Structure::Schematic { path, blocks: None } => {
+                return Err(format!("Schematic {} isn't loaded", path));
+            }
+        };
+        Ok(relative
+            .into_iter()
+            .filter(|b| !is_air(&b.block_type))
+            .map(|b| {
+                let position = origin.offset(b.offset, 1);
+                Block::new(position, b.block_type)
+            })
+            .collect())
+    }
+}
+
+fn is_air(block_type: &str) -> bool {
+    matches!(
+        block_type,
+        "a" | "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"
+    )
+}
+
+/// How many of each block the structure needs.
+pub fn materials(targets: &[Block]) -> BTreeMap<String, u32> {
+    let mut out = BTreeMap::new();
+    for block in targets.iter().filter(|b| b.is_solid()) {
+        *out.entry(block.block_type().to_string()).or_insert(0) += 1;
+    }
+    out
+}
+
+/// Result of comparing a structure with what turtles have reported.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildCheck {
+    pub placed: u32,
+    pub missing: Vec<Point3D>,
+    pub wrong: Vec<Point3D>,
+}
+
+pub fn verify(targets: &[Block], world: &World) -> BuildCheck {
+    let known = world.index();
+    let mut check = BuildCheck::default();
+    for target in targets {
+        match known.get(&target.position()) {
+            Some(b) if b.block_type() == target.block_type() => check.placed += 1,
+            Some(b) if b.is_solid() => check.wrong.push(target.position()),
+            _ => check.missing.push(target.position()),
+        }
+    }
+    check
+}
+
+/// Where `path` is under `dir`. Only plain relative paths to regular files
+/// inside it are allowed, so operators can't read anything else on the host.
+fn schematic_path(dir: &str, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!(
+            "Schematic path {:?} must be relative, without . or ..",
+            path
+        ));
+    }
+    let not_found = |e: std::io::Error| format!("Schematic {} not found: {}", path, e);
+    let dir = Path::new(dir).canonicalize().map_err(not_found)?;
+    let full = dir.join(relative).canonicalize().map_err(not_found)?;
+    // A symlink could still lead out of the directory
+    if !full.starts_with(&dir) {
+        return Err(format!(
+            "Schematic {} is outside the schematics directory",
+            path
+        ));
+    }
+    if !full.metadata().map_err(not_found)?.is_file() {
+        return Err(format!("Schematic {} isn't a file", path));
+    }
+    Ok(full)
+}
+
+// Minimal NBT reader, just enough for schematics
+
+// Limits on what a schematic can make the reader do: bytes decompressed,
+// elements in any one array or list, and how deep tags nest
+const MAX_SCHEMATIC_BYTES: u64 = 256 * 1024 * 1024;
+const MAX_ARRAY_LEN: usize = 64 * 1024 * 1024;
+const MAX_DEPTH: u32 = 32;
+
+#[derive(Debug)]
+enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<i32> {
+        match self {
+            Tag::Byte(v) => Some(*v as i32),
+            Tag::Short(v) => Some(*v as i32),
+            Tag::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+struct NbtReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> NbtReader<R> {
+    fn bytes<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn len(&mut self) -> std::io::Result<usize> {
+        let len = i32::from_be_bytes(self.bytes()?).max(0) as usize;
+        if len > MAX_ARRAY_LEN {
+            return Err(invalid(format!("NBT array of {} is too long", len)));
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        let len = u16::from_be_bytes(self.bytes()?) as usize;
+        let mut buf = vec![0u8; len];
+        self.inner.read_exact(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: u32) -> std::io::Result<Tag> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("NBT nests too deep".to_string()));
+        }
+        Ok(match id {
+            1 => Tag::Byte(i8::from_be_bytes(self.bytes()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.bytes()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.bytes()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.bytes()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.bytes()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.bytes()?)),
+            7 => {
+                let len = self.len()?;
+                let mut buf = vec![0u8; len];
+                self.inner.read_exact(&mut buf)?;
+                Tag::ByteArray(buf)
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let [item] = self.bytes()?;
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    items.push(self.payload(item, depth + 1)?);
+                }
+                Tag::List(items)
+            }
+            10 => {
+                let mut map = HashMap::new();
+                loop {
+                    let [child] = self.bytes()?;
+                    if child == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    map.insert(name, self.payload(child, depth + 1)?);
+                }
+                Tag::Compound(map)
+            }
+            11 => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    items.push(i32::from_be_bytes(self.bytes()?));
+                }
+                Tag::IntArray(items)
+            }
+            12 => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    items.push(i64::from_be_bytes(self.bytes()?));
+                }
+                Tag::LongArray(items)
+            }
+            _ => return Err(invalid(format!("unknown NBT tag {}", id))),
+        })
+    }
+
+    /// Read the named root tag.
+    fn root(&mut self) -> std::io::Result<Tag> {
+        let [id] = self.bytes()?;
+        let _name = self.string()?;
+        self.payload(id, 0)
+    }
+}
+
+fn invalid(text: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, text)
+}
+
+// `name` is the path as the job gave it, for errors.
+fn load_schematic(path: &Path, name: &str) -> Result<Vec<StructureBlock>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", name, e))?;
+    let mut reader = NbtReader {
+        inner: GzDecoder::new(BufReader::new(file)).take(MAX_SCHEMATIC_BYTES),
+    };
+    let root = reader
+        .root()
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    // Version 3 nests everything under "Schematic" and the blocks under "Blocks"
+    let schematic = root.get("Schematic").unwrap_or(&root);
+    let (palette, data) = match schematic.get("Blocks") {
+        Some(blocks) => (blocks.get("Palette"), blocks.get("Data")),
+        None => (schematic.get("Palette"), schematic.get("BlockData")),
+    };
+    let dim = |key: &str| {
+        schematic
+            .get(key)
+            .and_then(Tag::as_int)
+            .map(|v| v as u16 as usize)
+            .ok_or_else(|| format!("Schematic is missing {}", key))
+    };
+    let (width, height, length) = (dim("Width")?, dim("Height")?, dim("Length")?);
+
+    let Some(Tag::Compound(palette)) = palette else {
+        return Err("Schematic has no block palette".to_string());
+    };
+    let Some(Tag::ByteArray(data)) = data else {
+        return Err("Schematic has no block data".to_string());
+    };
+    let mut names: HashMap<i32, String> = HashMap::new();
+    for (state, index) in palette {
+        // Drop block states like "[facing=north]", turtles can't set them
+        let name = state.split('[').next().unwrap_or(state);
+        if let Some(i) = index.as_int() {
+            names.insert(i, name.to_string());
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut bytes = data.iter();
+    for i in 0..width * height * length {
+        let index = read_varint(&mut bytes).ok_or("Schematic block data ended early")?;
+        let Some(name) = names.get(&index) else {
+            return Err(format!("Schematic uses unknown palette index {}", index));
+        };
+        let x = i % width;
+        let z = (i / width) % length;
+        let y = i / (width * length);
+        out.push(StructureBlock {
+            offset: Point3D::new(x as i32, y as i32, z as i32),
+            block_type: name.clone(),
+        });
+    }
+    Ok(out)
+}
+
+fn read_varint<'a>(bytes: &mut impl Iterator<Item = &'a u8>) -> Option<i32> {
+    let mut value: i32 = 0;
+    for shift in (0..35).step_by(7) {
+        let b = *bytes.next()?;
+        value |= ((b & 0x7f) as i32) << shift;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
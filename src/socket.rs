@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{
+        State,
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    },
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tracing::info;
+
+use crate::auth::TurtleId;
+use crate::job::JobId;
+use crate::state::AppState;
+use crate::turtle::Item;
+use crate::{
+    FailureReport, Instructions, StatusUpdate, next_instructions, record_failure, record_inventory,
+    record_status, resume,
+};
+
+/// Pushed from the server to a connected turtle.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    /// A batch to run. The turtle acks each step as it finishes it.
+    Instructions(Instructions),
+    /// Drop whatever is left of the job's instructions.
+    Cancel {
+        job: JobId,
+    },
+    /// Stop moving right away.
+    Stop,
+    /// The turtle's message with this `seq` was applied and needn't be resent.
+    Ack {
+        seq: u64,
+    },
+    Error {
+        text: String,
+    },
+}
+
+/// Sent by a turtle over its socket. Everything but `ack` carries a `seq` the
+/// server acknowledges, so the turtle knows what to resend after reconnecting.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TurtleMessage {
+    Status {
+        seq: u64,
+        #[serde(flatten)]
+        update: StatusUpdate,
+    },
+    Inventory {
+        seq: u64,
+        inventory: Vec<Item>,
+    },
+    /// Ask for the next batch, same as polling `/get-instructions`.
+    Request {
+        seq: u64,
+    },
+    /// Step `seq` of batch `batch` is done.
+    Ack {
+        batch: u64,
+        seq: u32,
+    },
+    /// Step `step` of batch `batch` couldn't be done; the batch is abandoned.
+    Failure {
+        seq: u64,
+        batch: u64,
+        step: u32,
+        reason: String,
+    },
+}
+
+/// Open turtle sockets. Batches in flight are tracked on the turtle itself so
+/// they survive reconnects and reboots.
+#[derive(Default)]
+pub struct Connections {
+    senders: HashMap<u32, UnboundedSender<ServerMessage>>,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_connected(&self, turtle_id: u32) -> bool {
+        self.senders.contains_key(&turtle_id)
+    }
+
+    /// Push a message to a turtle. Returns false if it isn't connected.
+    pub fn send(&self, turtle_id: u32, msg: ServerMessage) -> bool {
+        match self.senders.get(&turtle_id) {
+            Some(tx) => tx.send(msg).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Work out and push the next batch to a connected turtle, if it has one.
+pub async fn push_instructions(st: &AppState, turtle_id: u32) {
+    if !st.connections.read().await.is_connected(turtle_id) {
+        return;
+    }
+    if let Ok(instructions) = next_instructions(st, turtle_id).await
+        && !instructions.steps.is_empty()
+    {
+        let connections = st.connections.read().await;
+        connections.send(turtle_id, ServerMessage::Instructions(instructions));
+    }
+}
+
+pub async fn turtle_socket(
+    ws: WebSocketUpgrade,
+    State(st): State<AppState>,
+    TurtleId(turtle_id): TurtleId,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, st, turtle_id))
+}
+
+async fn handle_socket(mut socket: WebSocket, st: AppState, turtle_id: u32) {
+    let (tx, mut rx) = unbounded_channel();
+    // A reconnect takes over from the old socket, whose loop ends when its
+    // connection finally drops
+    st.connections
+        .write()
+        .await
+        .senders
+        .insert(turtle_id, tx.clone());
+    if let Some(batch) = st
+        .turtles
+        .read()
+        .await
+        .get_turtle(turtle_id)
+        .and_then(|t| t.batch())
+        && !batch.is_finished()
+    {
+        info!(
+            "Resending unfinished batch {} to turtle {}",
+            batch.id, turtle_id
+        );
+        let _ = tx.send(ServerMessage::Instructions(resume(batch)));
+    }
+    info!("Turtle {} connected", turtle_id);
+
+    // Any frame from the turtle, pings included, keeps the socket open
+    let idle = st.config().await.heartbeat.socket_idle();
+    let deadline = tokio::time::sleep(idle);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => {
+                info!("Closing idle socket of turtle {}", turtle_id);
+                break;
+            }
+            outgoing = rx.recv() => {
+                let Some(msg) = outgoing else { break };
+                let text = serde_json::to_string(&msg).unwrap();
+                if socket.send(WsMessage::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                deadline.as_mut().reset(tokio::time::Instant::now() + idle);
+                st.turtles.write().await.seen(turtle_id);
+                let text = match incoming {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                if !st.credentials.read().await.contains(turtle_id) {
+                    info!("Closing socket of revoked turtle {}", turtle_id);
+                    break;
+                }
+                match serde_json::from_str::<TurtleMessage>(&text) {
+                    Ok(msg) => handle_message(&st, turtle_id, msg, &tx).await,
+                    Err(e) => {
+                        let _ = tx.send(ServerMessage::Error {
+                            text: format!("Bad message: {}", e),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let mut connections = st.connections.write().await;
+    if connections
+        .senders
+        .get(&turtle_id)
+        .is_some_and(|current| current.same_channel(&tx))
+    {
+        connections.senders.remove(&turtle_id);
+    }
+    info!("Turtle {} disconnected", turtle_id);
+}
+
+async fn handle_message(
+    st: &AppState,
+    turtle_id: u32,
+    msg: TurtleMessage,
+    tx: &UnboundedSender<ServerMessage>,
+) {
+    match msg {
+        TurtleMessage::Status { seq, update } => {
+            let current = record_status(st, Some(turtle_id), update).await;
+            let _ = tx.send(ServerMessage::Ack { seq });
+            if !current {
+                let _ = tx.send(ServerMessage::Stop);
+            }
+        }
+        TurtleMessage::Inventory { seq, inventory } => {
+            record_inventory(st, turtle_id, inventory).await;
+            let _ = tx.send(ServerMessage::Ack { seq });
+        }
+        TurtleMessage::Request { seq } => {
+            let _ = tx.send(ServerMessage::Ack { seq });
+            // Planning the trip can take a while, and Stop or Cancel has to
+            // reach the turtle in the meantime
+            let (st, tx) = (st.clone(), tx.clone());
+            tokio::spawn(async move {
+                match next_instructions(&st, turtle_id).await {
+                    Ok(instructions) => {
+                        let _ = tx.send(ServerMessage::Instructions(instructions));
+                    }
+                    Err((_, text)) => {
+                        let _ = tx.send(ServerMessage::Error {
+                            text: text.to_string(),
+                        });
+                    }
+                }
+            });
+        }
+        TurtleMessage::Ack { batch, seq } => {
+            st.turtles.write().await.ack(turtle_id, batch, seq);
+        }
+        TurtleMessage::Failure {
+            seq,
+            batch,
+            step,
+            reason,
+        } => {
+            let report = FailureReport {
+                batch,
+                step,
+                reason,
+            };
+            record_failure(st, turtle_id, report).await;
+            let _ = tx.send(ServerMessage::Ack { seq });
+        }
+    }
+}
@@ -1,21 +1,83 @@
+use crate::audit::AuditLog;
+use crate::auth::{Credentials, Nonces};
+use crate::config::{Args, Config};
+use crate::events::Events;
 use crate::job::Jobs;
-use crate::turtle::{Turtles, World};
+use crate::limit::RateLimiter;
+use crate::metrics::TimedRwLock;
+use crate::socket::Connections;
+use crate::turtle::{Turtles, Worlds};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::Semaphore;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub world: Arc<RwLock<World>>,
-    pub turtles: Arc<RwLock<Turtles>>,
-    pub jobs: Arc<RwLock<Jobs>>,
+    pub worlds: Arc<TimedRwLock<Worlds>>,
+    pub turtles: Arc<TimedRwLock<Turtles>>,
+    pub jobs: Arc<TimedRwLock<Jobs>>,
+    pub connections: Arc<TimedRwLock<Connections>>,
+    pub credentials: Arc<TimedRwLock<Credentials>>,
+    pub nonces: Arc<TimedRwLock<Nonces>>,
+    pub audit: Arc<TimedRwLock<AuditLog>>,
+    pub address_limiter: Arc<TimedRwLock<RateLimiter>>,
+    pub rate_limiter: Arc<TimedRwLock<RateLimiter>>,
+    pub path_limiter: Arc<TimedRwLock<RateLimiter>>,
+    /// Caps how many path searches run at once.
+    pub path_permits: Arc<Semaphore>,
+    /// Swapped whole on reload, see `config::reload`.
+    pub config: Arc<TimedRwLock<Arc<Config>>>,
+    /// What the config was loaded with, for reloading it.
+    pub args: Arc<Args>,
+    /// Shared with the turtle and job registries, which publish to it too.
+    pub events: Events,
 }
 
 impl AppState {
-    pub fn new(world: World, turtles: Turtles, jobs: Jobs) -> Self {
+    /// Turtles and jobs start out empty, publishing to a new event bus.
+    pub fn new(
+        worlds: Worlds,
+        credentials: Credentials,
+        audit: AuditLog,
+        config: Config,
+        args: Args,
+    ) -> Self {
+        let limits = config.limits.clone();
+        let events = Events::new();
+        let turtles = Turtles::new(events.clone());
+        let jobs = Jobs::new(events.clone());
         Self {
-            world: Arc::new(RwLock::new(world)),
-            turtles: Arc::new(RwLock::new(turtles)),
-            jobs: Arc::new(RwLock::new(jobs)),
+            worlds: Arc::new(TimedRwLock::new("worlds", worlds)),
+            turtles: Arc::new(TimedRwLock::new("turtles", turtles)),
+            jobs: Arc::new(TimedRwLock::new("jobs", jobs)),
+            connections: Arc::new(TimedRwLock::new("connections", Connections::new())),
+            credentials: Arc::new(TimedRwLock::new("credentials", credentials)),
+            nonces: Arc::new(TimedRwLock::new("nonces", Nonces::new())),
+            audit: Arc::new(TimedRwLock::new("audit", audit)),
+            address_limiter: Arc::new(TimedRwLock::new(
+                "address_limiter",
+                RateLimiter::new(limits.address_requests_per_second, limits.address_burst),
+            )),
+            rate_limiter: Arc::new(TimedRwLock::new(
+                "rate_limiter",
+                RateLimiter::new(limits.requests_per_second, limits.burst),
+            )),
+            path_limiter: Arc::new(TimedRwLock::new(
+                "path_limiter",
+                RateLimiter::new(
+                    limits.path_searches_per_minute / 60.0,
+                    limits.path_search_burst,
+                ),
+            )),
+            path_permits: Arc::new(Semaphore::new(limits.max_path_searches.max(1))),
+            config: Arc::new(TimedRwLock::new("config", Arc::new(config))),
+            args: Arc::new(args),
+            events,
         }
     }
+
+    /// The config in effect right now. Handlers take it once so a reload
+    /// halfway through can't mix old and new settings.
+    pub async fn config(&self) -> Arc<Config> {
+        self.config.read().await.clone()
+    }
 }
@@ -1,15 +1,20 @@
 use core::str;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use std::{fs::File, io::BufReader};
 
+use crate::config;
+use crate::events::{Event, Events};
+use crate::job::JobId;
 use crate::pathfinder::{Grid, Point3D, astar_find_path, path_to_moves};
-use bincode::{Decode, Encode, config};
-use serde::Deserialize;
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Encode, Decode, PartialEq, Debug, Deserialize)]
+#[derive(Encode, Decode, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     position: Point3D,
     block_type: String,
@@ -22,9 +27,186 @@ impl Block {
         }
     }
 
+    pub fn position(&self) -> Point3D {
+        self.position
+    }
+
+    pub fn block_type(&self) -> &str {
+        &self.block_type
+    }
+
     pub fn is_solid(&self) -> bool {
         self.block_type != "a" // air
     }
+
+    /// The block's id, with the cache's shorthand for air spelled out.
+    pub fn name(&self) -> &str {
+        if self.is_solid() {
+            &self.block_type
+        } else {
+            "minecraft:air"
+        }
+    }
+}
+
+/// A server the manager works on and a dimension on it. Each has its own
+/// block cache, since the same coordinates mean different places in each.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldId {
+    pub server: String,
+    pub dimension: String,
+}
+impl Default for WorldId {
+    fn default() -> Self {
+        WorldId {
+            server: "default".to_string(),
+            dimension: "minecraft:overworld".to_string(),
+        }
+    }
+}
+impl std::fmt::Display for WorldId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.server, self.dimension)
+    }
+}
+impl std::str::FromStr for WorldId {
+    type Err = String;
+
+    /// Parses `server/dimension`, the same as `Display` writes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((server, dimension)) if !server.is_empty() && !dimension.is_empty() => {
+                Ok(WorldId {
+                    server: server.to_string(),
+                    dimension: dimension.to_string(),
+                })
+            }
+            _ => Err("world must be server/dimension".to_string()),
+        }
+    }
+}
+impl WorldId {
+    /// `{dir}/{server}/{dimension}.bin`, with anything but letters, digits,
+    /// `-` and `_` escaped as `%XX` so names like `minecraft:the_nether`
+    /// make valid file names and can't point outside `dir`.
+    fn save_path(&self, dir: &Path) -> PathBuf {
+        dir.join(escape(&self.server))
+            .join(escape(&self.dimension) + ".bin")
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail.get(..2)?;
+            // from_str_radix would also take a sign, as in `%+1`
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// Handed out for worlds nothing has been reported in yet
+static EMPTY_WORLD: World = World::new();
+
+/// Every world's block cache.
+pub struct Worlds {
+    worlds: HashMap<WorldId, World>,
+}
+impl Worlds {
+    pub fn new() -> Self {
+        Worlds {
+            worlds: HashMap::new(),
+        }
+    }
+
+    /// The cache for `id`, empty if nothing has been reported there.
+    pub fn get(&self, id: &WorldId) -> &World {
+        self.worlds.get(id).unwrap_or(&EMPTY_WORLD)
+    }
+
+    pub fn get_mut(&mut self, id: &WorldId) -> &mut World {
+        self.worlds.entry(id.clone()).or_insert_with(World::new)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&WorldId, &World)> {
+        self.worlds.iter()
+    }
+
+    /// Blocks cached across every world.
+    pub fn block_count(&self) -> usize {
+        self.worlds.values().map(World::len).sum()
+    }
+
+    /// Load every world saved under `dir`. A missing directory is just no
+    /// worlds yet.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Worlds, Box<dyn std::error::Error>> {
+        let mut worlds = Worlds::new();
+        let servers = match std::fs::read_dir(&dir) {
+            Ok(servers) => servers,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(worlds),
+            Err(e) => return Err(Box::new(e)),
+        };
+        for server in servers {
+            let server = server?;
+            if !server.file_type()?.is_dir() {
+                continue;
+            }
+            let Some(server_name) = server.file_name().to_str().and_then(unescape) else {
+                continue;
+            };
+            for file in std::fs::read_dir(server.path())? {
+                let path = file?.path();
+                let Some(dimension) = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.strip_suffix(".bin"))
+                    .and_then(unescape)
+                else {
+                    continue;
+                };
+                let id = WorldId {
+                    server: server_name.clone(),
+                    dimension,
+                };
+                let mut world = World::new();
+                world.load_world(&path)?;
+                info!("Loaded {} blocks in {}", world.len(), id);
+                worlds.worlds.insert(id, world);
+            }
+        }
+        Ok(worlds)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), Box<dyn std::error::Error>> {
+        for (id, world) in &self.worlds {
+            let path = id.save_path(dir.as_ref());
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            world.save_world(&path)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Encode, Decode, PartialEq, Debug)]
@@ -32,7 +214,7 @@ pub struct World {
     blocks: Vec<Block>,
 }
 impl World {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         World { blocks: Vec::new() }
     }
     pub fn add_block(&mut self, block: Block) {
@@ -44,7 +226,8 @@ impl World {
     pub fn get_block_mut(&mut self, position: Point3D) -> Option<&mut Block> {
         self.blocks.iter_mut().find(|b| b.position == position)
     }
-    pub fn set_block(&mut self, mut block: Block) {
+    /// Returns whether the cache changed.
+    pub fn set_block(&mut self, mut block: Block) -> bool {
         // setting to "a" for the sake of saving storage
         // i would just not store it, but it might be useful
         // to know exactly what has been explored and what hasn't
@@ -52,33 +235,104 @@ impl World {
             block.block_type = "a".to_string();
         }
         if let Some(existing) = self.get_block_mut(block.position) {
+            if *existing == block {
+                return false;
+            }
             *existing = block;
         } else {
             self.add_block(block);
         }
+        true
+    }
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Every cached block keyed by position, for bulk lookups.
+    pub fn index(&self) -> HashMap<Point3D, &Block> {
+        self.blocks.iter().map(|b| (b.position, b)).collect()
+    }
+    /// Cached blocks in the box between two corners.
+    pub fn blocks_in(&self, a: Point3D, b: Point3D) -> impl Iterator<Item = &Block> {
+        let min = Point3D::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Point3D::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        self.blocks.iter().filter(move |b| {
+            let p = b.position;
+            p.x >= min.x
+                && p.x <= max.x
+                && p.y >= min.y
+                && p.y <= max.y
+                && p.z >= min.z
+                && p.z <= max.z
+        })
+    }
+    /// Every cached position mapped to whether it is solid.
+    pub fn solid_map(&self) -> HashMap<Point3D, bool> {
+        self.blocks
+            .iter()
+            .map(|b| (b.position, b.is_solid()))
+            .collect()
+    }
+    /// Apply many block updates at once. Looks each position up in a single
+    /// index instead of scanning the cache per block like `set_block`.
+    /// Returns the blocks that changed.
+    pub fn set_blocks(&mut self, blocks: Vec<Block>) -> Vec<Block> {
+        let mut changed = Vec::new();
+        let mut index: HashMap<Point3D, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (b.position, i))
+            .collect();
+        for mut block in blocks {
+            if block.block_type == "minecraft:air" {
+                block.block_type = "a".to_string();
+            }
+            match index.get(&block.position) {
+                Some(&i) if self.blocks[i] == block => continue,
+                Some(&i) => self.blocks[i] = block.clone(),
+                None => {
+                    index.insert(block.position, self.blocks.len());
+                    self.blocks.push(block.clone());
+                }
+            }
+            changed.push(block);
+        }
+        changed
     }
-    pub fn get_path(
+
+    /// Build the grid for a path search from the cached blocks. Returns it
+    /// with `end` clamped to the world's height limits, or None if the grid
+    /// would be over `options.max_grid_cells`. Searching it with `find_moves`
+    /// doesn't need the world, so it can run without its lock.
+    pub fn path_grid(
         &self,
         start: Point3D,
         mut end: Point3D,
-        padding: u32,
-        can_dig: bool,
-    ) -> Option<Vec<String>> {
-        end.y = end.y.min(318);
-        end.y = end.y.max(-60);
-        println!("Finding path from {:?} to {:?}", start, end);
+        options: &config::Pathfinder,
+        limits: config::HeightLimits,
+    ) -> Result<(Grid, Point3D), &'static str> {
+        end.y = end.y.min(limits.max_y);
+        end.y = end.y.max(limits.min_y);
+        debug!("Finding path from {:?} to {:?}", start, end);
 
-        let mut min = Point3D::new(start.x.min(end.x), start.y.min(end.y), start.z.min(end.z));
-        let mut max = Point3D::new(start.x.max(end.x), start.y.max(end.y), start.z.max(end.z));
-
-        min.x -= padding as i32;
-        min.y -= padding as i32;
-        min.z -= padding as i32;
-        max.x += padding as i32;
-        max.y += padding as i32;
-        max.z += padding as i32;
-
-        println!("Using grid from {:?} to {:?}", min, max);
+        let (min, max) =
+            search_bounds(start, end, options.padding).ok_or("Path search out of range")?;
+        debug!("Using grid from {:?} to {:?}", min, max);
+        let cells = (max.x.abs_diff(min.x) as u64 + 1)
+            * (max.y.abs_diff(min.y) as u64 + 1)
+            * (max.z.abs_diff(min.z) as u64 + 1);
+        if cells > options.max_grid_cells as u64 {
+            debug!(
+                "Grid of {} cells is over the limit of {}",
+                cells, options.max_grid_cells
+            );
+            return Err("Path search too large");
+        }
 
         let mut grid = Grid::new(min, max, 1);
         grid.set_cost(start, 1);
@@ -87,29 +341,14 @@ impl World {
         // blocks within the grid bounds
         for block in &self.blocks {
             if block.is_solid() && grid.in_bounds(block.position) {
-                if can_dig && block.block_type != "minecraft:bedrock" {
+                if options.can_dig && block.block_type != "minecraft:bedrock" {
                     grid.set_cost(block.position, 2);
                 } else {
                     grid.set_cost(block.position, 0);
                 }
             }
         }
-        match astar_find_path(&grid, start, end) {
-            Some(path) => match path_to_moves(&grid, &path) {
-                Ok(moves) => {
-                    println!("Path found with {} moves", moves.len());
-                    Some(moves)
-                }
-                Err(e) => {
-                    println!("Error converting path to moves: {}", e);
-                    None
-                }
-            },
-            None => {
-                println!("No path found");
-                None
-            }
-        }
+        Ok((grid, end))
     }
     pub fn load_world<P: AsRef<Path>>(
         &mut self,
@@ -118,7 +357,7 @@ impl World {
         match File::open(&path) {
             Ok(file) => {
                 let mut reader = BufReader::new(file);
-                let cfg = config::standard();
+                let cfg = bincode::config::standard();
                 let loaded: World = bincode::decode_from_std_read(&mut reader, cfg)?;
                 *self = loaded;
                 Ok(())
@@ -130,25 +369,144 @@ impl World {
             Err(e) => Err(Box::new(e)),
         }
     }
-    pub fn save_world(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save_world<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
         let mut file = File::create(path)?;
-        let cfg = config::standard();
+        let cfg = bincode::config::standard();
         bincode::encode_into_std_write(self, &mut file, cfg)?;
         Ok(())
     }
 }
 
+/// Corners of the box searched between `start` and `end`, or None if the
+/// padding takes it outside the i32 range.
+pub fn search_bounds(start: Point3D, end: Point3D, padding: u32) -> Option<(Point3D, Point3D)> {
+    let padding = i32::try_from(padding).ok()?;
+    let min = Point3D::new(
+        start.x.min(end.x).checked_sub(padding)?,
+        start.y.min(end.y).checked_sub(padding)?,
+        start.z.min(end.z).checked_sub(padding)?,
+    );
+    let max = Point3D::new(
+        start.x.max(end.x).checked_add(padding)?,
+        start.y.max(end.y).checked_add(padding)?,
+        start.z.max(end.z).checked_add(padding)?,
+    );
+    Some((min, max))
+}
+
+/// Run A* over a grid from `World::path_grid` and turn the result into moves.
+pub fn find_moves(grid: &Grid, start: Point3D, end: Point3D) -> Option<Vec<String>> {
+    match astar_find_path(grid, start, end) {
+        Some(path) => match path_to_moves(grid, &path) {
+            Ok(moves) => {
+                debug!("Path found with {} moves", moves.len());
+                Some(moves)
+            }
+            Err(e) => {
+                warn!("Error converting path to moves: {}", e);
+                None
+            }
+        },
+        None => {
+            debug!("No path found");
+            None
+        }
+    }
+}
+
+/// A batch of instructions sent to a turtle. Step `i` has sequence number
+/// `i`; `done` counts the steps the turtle has acknowledged.
+pub struct Batch {
+    pub id: u64,
+    pub steps: Vec<String>,
+    pub done: u32,
+    /// Moves of step `done` already made, when it's a counted move like
+    /// `forward 37`.
+    pub moved: u32,
+    pub job: Option<JobId>,
+}
+impl Batch {
+    pub fn is_finished(&self) -> bool {
+        self.done as usize >= self.steps.len()
+    }
+}
+
+/// An admin override that takes priority over any job the turtle has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hold {
+    /// Stay put until released.
+    Halted,
+    /// Head home, then stay there until released.
+    Recalled,
+}
+
+/// A fuel level as CC:Tweaked reports it: a number, or `"unlimited"` on
+/// servers with fuel turned off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "FuelReport")]
+pub enum Fuel {
+    Level(u32),
+    Unlimited,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FuelReport {
+    Level(u32),
+    Text(String),
+}
+
+impl TryFrom<FuelReport> for Fuel {
+    type Error = String;
+
+    fn try_from(report: FuelReport) -> Result<Self, Self::Error> {
+        match report {
+            FuelReport::Level(level) => Ok(Fuel::Level(level)),
+            FuelReport::Text(text) if text == "unlimited" => Ok(Fuel::Unlimited),
+            FuelReport::Text(text) => Err(format!("Invalid fuel level {:?}", text)),
+        }
+    }
+}
+
+impl Serialize for Fuel {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Fuel::Level(level) => serializer.serialize_u32(*level),
+            Fuel::Unlimited => serializer.serialize_str("unlimited"),
+        }
+    }
+}
+
+impl Fuel {
+    /// Enough to make `moves` moves.
+    pub fn covers(self, moves: u32) -> bool {
+        match self {
+            Fuel::Level(level) => level >= moves,
+            Fuel::Unlimited => true,
+        }
+    }
+}
+
 pub struct Turtle {
+    world: WorldId,
     position: Point3D,
+    home: (WorldId, Point3D), // where it first registered
     id: u32,
-    facing: u8, // 0: north, 1: south, 2: west, 3: east
+    facing: u8,         // 0: north, 1: east, 2: south, 3: west (same as creep.lua)
+    fuel: Option<Fuel>, // None until the turtle reports it
     name: String,
     status: String,
     last_heartbeat: Instant,
     inventory: Vec<Item>,
+    batch: Option<Batch>,
+    hold: Option<Hold>,
+    // Set once `turtle_lost` is sent, until it's heard from again
+    lost: bool,
 }
 impl Turtle {
     pub fn new(
+        world: WorldId,
         position: Point3D,
         id: u32,
         facing: u8,
@@ -157,24 +515,87 @@ impl Turtle {
         inventory: Vec<Item>,
     ) -> Self {
         Turtle {
+            home: (world.clone(), position),
+            world,
             position,
             id,
             facing,
+            fuel: None,
             name,
             status,
             last_heartbeat: Instant::now(),
             inventory,
+            batch: None,
+            hold: None,
+            lost: false,
         }
     }
+
+    pub fn batch(&self) -> Option<&Batch> {
+        self.batch.as_ref()
+    }
+
+    pub fn hold(&self) -> Option<Hold> {
+        self.hold
+    }
+
+    pub fn home(&self) -> (&WorldId, Point3D) {
+        (&self.home.0, self.home.1)
+    }
+
+    pub fn world(&self) -> &WorldId {
+        &self.world
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Point3D {
+        self.position
+    }
+
+    pub fn facing(&self) -> u8 {
+        self.facing
+    }
+
+    pub fn fuel(&self) -> Option<Fuel> {
+        self.fuel
+    }
+
+    pub fn has_item(&self, name: &str) -> bool {
+        self.inventory.iter().any(|i| i.name == name && i.count > 0)
+    }
+
+    pub fn last_heartbeat(&self) -> Instant {
+        self.last_heartbeat
+    }
+
+    /// A report without a fuel level keeps the last one known.
+    pub fn update(&mut self, position: Point3D, facing: u8, fuel: Option<Fuel>) {
+        self.position = position;
+        self.facing = facing;
+        self.fuel = fuel.or(self.fuel);
+        self.last_heartbeat = Instant::now();
+        self.lost = false;
+    }
 }
 
 pub struct Turtles {
     turtles: Vec<Turtle>,
+    next_batch: u64,
+    events: Events,
 }
 impl Turtles {
-    pub fn new() -> Self {
+    pub fn new(events: Events) -> Self {
         Turtles {
             turtles: Vec::new(),
+            next_batch: 0,
+            events,
         }
     }
 
@@ -185,9 +606,290 @@ impl Turtles {
     pub fn get_turtle(&self, id: u32) -> Option<&Turtle> {
         self.turtles.iter().find(|t| t.id == id)
     }
+
+    pub fn get_turtle_mut(&mut self, id: u32) -> Option<&mut Turtle> {
+        self.turtles.iter_mut().find(|t| t.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Turtle> {
+        self.turtles.iter()
+    }
+
+    /// Hand a turtle a new batch, replacing whatever it had. Returns the id.
+    pub fn start_batch(&mut self, id: u32, steps: Vec<String>, job: Option<JobId>) -> u64 {
+        self.next_batch += 1;
+        let batch = self.next_batch;
+        if let Some(turtle) = self.get_turtle_mut(id) {
+            turtle.batch = Some(Batch {
+                id: batch,
+                steps,
+                done: 0,
+                moved: 0,
+                job,
+            });
+        }
+        batch
+    }
+
+    /// Record that a turtle has finished step `seq` of a batch. Returns false
+    /// if the batch isn't the turtle's current one any more, e.g. because it
+    /// was stopped, so it should give up on the rest.
+    pub fn ack(&mut self, id: u32, batch: u64, seq: u32) -> bool {
+        match self
+            .get_turtle_mut(id)
+            .and_then(|t| t.batch.as_mut())
+            .filter(|b| b.id == batch)
+        {
+            Some(current) => {
+                if seq + 1 > current.done {
+                    current.done = seq + 1;
+                    current.moved = 0;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record that a turtle has made `moved` of the moves of counted step
+    /// `seq`. Returns false like `ack` does.
+    pub fn progress(&mut self, id: u32, batch: u64, seq: u32, moved: u32) -> bool {
+        match self
+            .get_turtle_mut(id)
+            .and_then(|t| t.batch.as_mut())
+            .filter(|b| b.id == batch)
+        {
+            Some(current) => {
+                // Steps before it are done too, or it couldn't have started
+                if seq > current.done {
+                    current.done = seq;
+                    current.moved = 0;
+                }
+                if seq == current.done {
+                    current.moved = current.moved.max(moved);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop a turtle's batch because step `seq` failed. Returns the batch so
+    /// the caller can deal with its job.
+    pub fn fail(&mut self, id: u32, batch: u64, seq: u32) -> Option<Batch> {
+        let turtle = self.get_turtle_mut(id)?;
+        if turtle.batch.as_ref().is_some_and(|b| b.id == batch) {
+            let mut failed = turtle.batch.take()?;
+            failed.done = seq;
+            return Some(failed);
+        }
+        None
+    }
+
+    /// Registered turtles among `ids`, or every turtle if `ids` is None.
+    pub fn select(&self, ids: Option<&[u32]>) -> Vec<u32> {
+        self.turtles
+            .iter()
+            .map(|t| t.id)
+            .filter(|id| ids.is_none_or(|ids| ids.contains(id)))
+            .collect()
+    }
+
+    /// Put a turtle on hold, dropping whatever batch it was running.
+    pub fn set_hold(&mut self, id: u32, hold: Hold) {
+        if let Some(turtle) = self.get_turtle_mut(id) {
+            turtle.batch = None;
+            turtle.hold = Some(hold);
+        }
+    }
+
+    pub fn release(&mut self, id: u32) {
+        if let Some(turtle) = self.get_turtle_mut(id) {
+            turtle.hold = None;
+        }
+    }
+
+    pub fn clear_batch(&mut self, id: u32) {
+        if let Some(turtle) = self.get_turtle_mut(id) {
+            turtle.batch = None;
+        }
+    }
+
+    /// Replace a registered turtle's inventory with a fresh report.
+    pub fn set_inventory(&mut self, id: u32, inventory: Vec<Item>) -> bool {
+        match self.turtles.iter_mut().find(|t| t.id == id) {
+            Some(turtle) => {
+                turtle.inventory = inventory;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Note that a turtle is still around without it reporting a position,
+    /// e.g. when it polls for instructions.
+    pub fn seen(&mut self, id: u32) {
+        if let Some(turtle) = self.get_turtle_mut(id) {
+            turtle.last_heartbeat = Instant::now();
+            turtle.lost = false;
+        }
+    }
+
+    /// Send `turtle_lost` for turtles silent for longer than `offline_after`,
+    /// once each time they go quiet.
+    pub fn find_lost(&mut self, offline_after: Duration) {
+        for turtle in self.turtles.iter_mut() {
+            if !turtle.lost && turtle.last_heartbeat.elapsed() > offline_after {
+                warn!("Turtle {} went quiet", turtle.id);
+                turtle.lost = true;
+                self.events.publish(Event::TurtleLost { turtle: turtle.id });
+            }
+        }
+    }
+
+    /// Record a status report, registering the turtle the first time it's seen.
+    /// Turtles that don't say which world they're in stay where they were,
+    /// or start in the default world. A known fuel level dropping below
+    /// `low_fuel` sends `turtle_low_fuel`.
+    pub fn report(
+        &mut self,
+        id: u32,
+        world: Option<WorldId>,
+        position: Point3D,
+        facing: u8,
+        fuel: Option<Fuel>,
+        low_fuel: u32,
+    ) {
+        let low = match fuel {
+            Some(Fuel::Level(level)) if level < low_fuel => Some(level),
+            _ => None,
+        };
+        match self.turtles.iter_mut().find(|t| t.id == id) {
+            Some(turtle) => {
+                let moved = world.as_ref().is_some_and(|w| *w != turtle.world)
+                    || position != turtle.position
+                    || facing != turtle.facing;
+                let was_low = matches!(turtle.fuel, Some(Fuel::Level(level)) if level < low_fuel);
+                if let Some(world) = world {
+                    turtle.world = world;
+                }
+                turtle.update(position, facing, fuel);
+                if moved {
+                    self.events.publish(Event::TurtleMoved {
+                        turtle: id,
+                        world: turtle.world.clone(),
+                        position,
+                        facing,
+                    });
+                }
+                if let Some(fuel) = low
+                    && !was_low
+                {
+                    self.events
+                        .publish(Event::TurtleLowFuel { turtle: id, fuel });
+                }
+            }
+            None => {
+                let world = world.unwrap_or_default();
+                info!("Registered turtle {} at {:?} in {}", id, position, world);
+                self.events.publish(Event::TurtleRegistered {
+                    turtle: id,
+                    world: world.clone(),
+                    position,
+                });
+                if let Some(fuel) = low {
+                    self.events
+                        .publish(Event::TurtleLowFuel { turtle: id, fuel });
+                }
+                let mut turtle = Turtle::new(
+                    world,
+                    position,
+                    id,
+                    facing,
+                    format!("turtle-{}", id),
+                    "idle".to_string(),
+                    Vec::new(),
+                );
+                turtle.fuel = fuel;
+                self.add_turtle(turtle);
+            }
+        }
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     name: String,
     count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_bounds_past_i32_are_refused() {
+        let start = Point3D::new(0, 64, 0);
+        assert_eq!(
+            search_bounds(start, Point3D::new(5, 60, -3), 2),
+            Some((Point3D::new(-2, 58, -5), Point3D::new(7, 66, 2)))
+        );
+        assert_eq!(search_bounds(start, Point3D::new(i32::MAX, 64, 0), 1), None);
+        assert_eq!(search_bounds(start, Point3D::new(0, 64, i32::MIN), 1), None);
+        assert_eq!(search_bounds(start, start, u32::MAX), None);
+    }
+
+    #[test]
+    fn escape_of_nothing() {
+        assert_eq!(escape(""), "");
+        assert_eq!(unescape(""), Some(String::new()));
+    }
+
+    #[test]
+    fn plain_names_are_left_alone() {
+        assert_eq!(escape("default"), "default");
+        assert_eq!(escape("My-Server_2"), "My-Server_2");
+    }
+
+    #[test]
+    fn separators_are_escaped() {
+        assert_eq!(escape("minecraft:the_nether"), "minecraft%3Athe_nether");
+        assert_eq!(escape("../up"), "%2E%2E%2Fup");
+        assert_eq!(escape("a\\b"), "a%5Cb");
+        assert_eq!(escape("100%"), "100%25");
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for name in [
+            "",
+            "x",
+            "minecraft:overworld",
+            "../../etc/passwd",
+            "C:\\worlds",
+            "50% off",
+            "%41",
+            "grüße 世界",
+        ] {
+            assert_eq!(unescape(&escape(name)).as_deref(), Some(name));
+        }
+    }
+
+    #[test]
+    fn unescape_takes_either_case() {
+        assert_eq!(
+            unescape("minecraft%3athe_nether").as_deref(),
+            Some("minecraft:the_nether")
+        );
+    }
+
+    #[test]
+    fn unescape_rejects_bad_escapes() {
+        assert_eq!(unescape("%"), None);
+        assert_eq!(unescape("%4"), None);
+        assert_eq!(unescape("%G1"), None);
+        assert_eq!(unescape("%+1"), None);
+        // Not UTF-8
+        assert_eq!(unescape("%FF"), None);
+    }
+}